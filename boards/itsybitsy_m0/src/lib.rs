@@ -23,8 +23,7 @@ define_pins!(
     target_device: atsamd21g18a,
 
     /// Analog pin 0.  Can act as a true analog output
-    /// as it has a DAC (which is not currently supported
-    /// by this hal) as well as input.
+    /// as it has a DAC (see `hal::dac::Dac`) as well as input.
     pin a0 = a2,
     /// Analog Pin 1
     pin a1 = b8,
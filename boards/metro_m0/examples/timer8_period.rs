@@ -0,0 +1,51 @@
+//! On-target check that an 8-bit timer counter is programmed with the
+//! requested period rather than the reset value of 0xff.  The result is
+//! reported over semihosting; a mismatch panics.
+#![no_std]
+#![no_main]
+
+extern crate cortex_m;
+extern crate cortex_m_semihosting;
+extern crate metro_m0 as hal;
+#[macro_use]
+extern crate nb;
+extern crate panic_semihosting;
+
+use core::fmt::Write;
+use cortex_m_semihosting::hio;
+use hal::clock::GenericClockController;
+use hal::prelude::*;
+use hal::timer::TimerCounter3;
+use hal::{entry, Peripherals, TC3};
+
+#[entry]
+fn main() -> ! {
+    let mut peripherals = Peripherals::take().unwrap();
+    let mut clocks = GenericClockController::with_external_32kosc(
+        peripherals.GCLK,
+        &mut peripherals.PM,
+        &mut peripherals.SYSCTRL,
+        &mut peripherals.NVMCTRL,
+    );
+    let gclk0 = clocks.gclk0();
+    let tc_clock = clocks.tcc2_tc3(&gclk0).unwrap();
+    let mut timer =
+        TimerCounter3::tc3_(&tc_clock, peripherals.TC3, &mut peripherals.PM).into_8bit();
+
+    // 48Mhz / 256 / 1Khz is 187.5 cycles, which rounds to a TOP of 187
+    timer.try_start(1.khz()).unwrap();
+
+    // This is safe because we only read the register back
+    let per = unsafe { &*TC3::ptr() }.count8().per.read().per().bits();
+    let mut stdout = hio::hstdout().unwrap();
+    writeln!(stdout, "PER = {}", per).unwrap();
+    assert_eq!(per, 187);
+
+    // Each overflow should now take about a millisecond
+    for _ in 0..1000 {
+        block!(timer.wait()).unwrap();
+    }
+    writeln!(stdout, "1000 overflows counted").unwrap();
+
+    loop {}
+}
@@ -58,17 +58,17 @@ const APP: () = {
             .sercom0_core(&gclk2)
             .expect("Could not configure sercom0 core clock");
 
-        let uart = UART0::new(
+        let mut uart = UART0::new(
             &uart_clk,
             9600.hz(),
             device.SERCOM0,
-            unsafe { &mut hal::CorePeripherals::steal().NVIC },
             &mut device.PM,
             UART0Pinout::Rx3Tx2 {
                 rx: rx_pin,
                 tx: tx_pin,
             },
         );
+        uart.enable_rx_interrupt();
 
         let mut rx_led = pins.rx_led.into_open_drain_output(&mut pins.port);
         let mut tx_led = pins.tx_led.into_open_drain_output(&mut pins.port);
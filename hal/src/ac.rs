@@ -0,0 +1,382 @@
+//! Working with the Analog Comparator.
+//! The AC hardware has two comparator channels, each of which compares
+//! a positive input pin against either another pin or one of the
+//! internal references.  The comparison result can be polled, raise an
+//! interrupt, be routed to an output pin or generate an event that
+//! drives another peripheral via the event system.
+//! `AnalogComparator::new` powers up the hardware and returns the
+//! `Comparator0` and `Comparator1` handles used to configure the
+//! individual channels.
+use clock;
+use gpio::{Pa12, Pa13, Pa18, Pa19, Pa4, Pa5, Pa6, Pa7, PfB, PfH};
+use target_device::{AC, PM};
+
+/// The pins that can be connected to the comparator inputs.
+/// These are the AC AIN[0] through AIN[3] pins, configured
+/// for peripheral function B.
+pub enum AcPin {
+    Pa4(Pa4<PfB>),
+    Pa5(Pa5<PfB>),
+    Pa6(Pa6<PfB>),
+    Pa7(Pa7<PfB>),
+}
+
+impl AcPin {
+    /// Returns the mux value that selects this pin
+    fn mux(&self) -> u8 {
+        match self {
+            &AcPin::Pa4(_) => 0,
+            &AcPin::Pa5(_) => 1,
+            &AcPin::Pa6(_) => 2,
+            &AcPin::Pa7(_) => 3,
+        }
+    }
+}
+
+impl From<Pa4<PfB>> for AcPin {
+    fn from(pin: Pa4<PfB>) -> Self {
+        AcPin::Pa4(pin)
+    }
+}
+
+impl From<Pa5<PfB>> for AcPin {
+    fn from(pin: Pa5<PfB>) -> Self {
+        AcPin::Pa5(pin)
+    }
+}
+
+impl From<Pa6<PfB>> for AcPin {
+    fn from(pin: Pa6<PfB>) -> Self {
+        AcPin::Pa6(pin)
+    }
+}
+
+impl From<Pa7<PfB>> for AcPin {
+    fn from(pin: Pa7<PfB>) -> Self {
+        AcPin::Pa7(pin)
+    }
+}
+
+/// The negative input of a comparator may be a pin or
+/// one of the internal references.
+pub enum NegativeInput {
+    /// An AC input pin
+    Pin(AcPin),
+    /// Ground
+    Gnd,
+    /// VDDANA scaled by `(value + 1) / 64`; `value` must be in
+    /// the range 0 to 63.
+    Vscale(u8),
+    /// The internal bandgap reference
+    Bandgap,
+    /// The DAC output
+    Dac,
+}
+
+/// Selects the condition that raises the comparator interrupt
+/// or event.
+#[derive(Clone, Copy, Debug)]
+pub enum InterruptMode {
+    /// The output changed state
+    Toggle,
+    /// The output went from low to high
+    Rising,
+    /// The output went from high to low
+    Falling,
+    /// A single shot comparison completed
+    EndOfCompare,
+}
+
+/// The digital filter applied to the comparator output
+#[derive(Clone, Copy, Debug)]
+pub enum Filter {
+    Off,
+    /// Majority of 3 consecutive samples
+    Majority3,
+    /// Majority of 5 consecutive samples
+    Majority5,
+}
+
+/// Controls how the comparator output is driven to the output pin
+#[derive(Clone, Copy, Debug)]
+pub enum OutputMode {
+    /// The output pin is not driven
+    Off,
+    /// The raw, unfiltered comparator output
+    Async,
+    /// The filtered comparator output, synchronized to the
+    /// digital clock
+    Sync,
+}
+
+/// Configuration for a comparator channel
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    /// Enables hysteresis to reject noise around the threshold
+    pub hysteresis: bool,
+    /// When true, a comparison is only performed when requested via
+    /// `start` or an incoming event.  When false, the comparator
+    /// runs continuously.
+    pub single_shot: bool,
+    /// Selects the high speed, higher power mode of the comparator
+    pub high_speed: bool,
+    pub interrupt: InterruptMode,
+    pub filter: Filter,
+    pub output: OutputMode,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            hysteresis: false,
+            single_shot: false,
+            high_speed: true,
+            interrupt: InterruptMode::Toggle,
+            filter: Filter::Off,
+            output: OutputMode::Off,
+        }
+    }
+}
+
+/// `AnalogComparator` owns the AC peripheral.
+pub struct AnalogComparator {
+    ac: AC,
+}
+
+impl AnalogComparator {
+    /// Power up the analog comparator and return it together with the
+    /// handles for its two comparator channels.
+    /// The AC requires both its digital and analog clocks to have
+    /// been configured via the `GenericClockController`.
+    pub fn new(
+        _dig_clock: &clock::AcDigClock,
+        _ana_clock: &clock::AcAnaClock,
+        ac: AC,
+        pm: &mut PM,
+    ) -> (Self, Comparator0, Comparator1) {
+        // this is safe because we're constrained to just the ac bit
+        pm.apbcmask.modify(|_, w| w.ac_().set_bit());
+
+        ac.ctrla.write(|w| w.swrst().set_bit());
+        while ac.ctrla.read().bits() & 1 != 0 || ac.statusb.read().syncbusy().bit_is_set() {}
+
+        ac.ctrla.modify(|_, w| w.enable().set_bit());
+        while ac.statusb.read().syncbusy().bit_is_set() {}
+
+        (
+            Self { ac },
+            Comparator0 {
+                inputs: None,
+                output: None,
+            },
+            Comparator1 {
+                inputs: None,
+                output: None,
+            },
+        )
+    }
+
+    /// Configure whether the comparators keep running in standby sleep
+    /// mode, so that a comparison can wake the device.  The AC clock
+    /// generators must also run in standby.
+    pub fn run_in_standby(&mut self, enabled: bool) {
+        let was_enabled = self.ac.ctrla.read().enable().bit_is_set();
+        self.ac.ctrla.modify(|_, w| w.enable().clear_bit());
+        while self.ac.statusb.read().syncbusy().bit_is_set() {}
+        self.ac.ctrla.modify(|_, w| {
+            w.runstdby().bit(enabled);
+            w.enable().bit(was_enabled)
+        });
+        while self.ac.statusb.read().syncbusy().bit_is_set() {}
+    }
+
+    /// Disable the analog comparator and release the AC peripheral.
+    /// Both comparator handles must be returned so that their pins
+    /// have been released.
+    pub fn free(self, _comp0: Comparator0, _comp1: Comparator1) -> AC {
+        self.ac.ctrla.modify(|_, w| w.enable().clear_bit());
+        while self.ac.statusb.read().syncbusy().bit_is_set() {}
+        self.ac
+    }
+}
+
+macro_rules! comparator {
+    ($($Type:ident: ($Output:ident, $idx:expr, $state:ident, $ready:ident,
+        $start:ident, $comp:ident, $compeo:ident, $compei:ident,
+        [$($Pin:ident),+]),)+) => {
+        $(
+
+/// The pins that can be driven by the comparator output.
+/// These must be configured for peripheral function H.
+pub enum $Output {
+    $(
+        $Pin($Pin<PfH>),
+    )+
+}
+
+$(
+impl From<$Pin<PfH>> for $Output {
+    fn from(pin: $Pin<PfH>) -> Self {
+        $Output::$Pin(pin)
+    }
+}
+)+
+
+/// A handle to the comparator channel with the matching number.
+pub struct $Type {
+    inputs: Option<(AcPin, NegativeInput)>,
+    output: Option<$Output>,
+}
+
+impl $Type {
+    fn ac(&self) -> &::target_device::ac::RegisterBlock {
+        // This is safe because each handle only touches the registers
+        // and bits that belong to its own channel.
+        unsafe { &*AC::ptr() }
+    }
+
+    fn wait_for_sync(&self) {
+        while self.ac().statusb.read().syncbusy().bit_is_set() {}
+    }
+
+    /// Configure and enable the comparator to compare `positive`
+    /// against `negative`.
+    /// Returns the inputs that were previously in use, if any.
+    pub fn enable(
+        &mut self,
+        positive: AcPin,
+        negative: NegativeInput,
+        config: Config,
+    ) -> Option<(AcPin, NegativeInput)> {
+        let previous = self.disable();
+
+        if let NegativeInput::Vscale(value) = negative {
+            self.ac().scaler[$idx].write(|w| unsafe { w.value().bits(value & 0x3f) });
+        }
+
+        self.ac().compctrl[$idx].write(|w| {
+            w.muxpos().bits(positive.mux());
+            match negative {
+                NegativeInput::Pin(ref pin) => w.muxneg().bits(pin.mux()),
+                NegativeInput::Gnd => w.muxneg().gnd(),
+                NegativeInput::Vscale(_) => w.muxneg().vscale(),
+                NegativeInput::Bandgap => w.muxneg().bandgap(),
+                NegativeInput::Dac => w.muxneg().dac(),
+            };
+            match config.interrupt {
+                InterruptMode::Toggle => w.intsel().toggle(),
+                InterruptMode::Rising => w.intsel().rising(),
+                InterruptMode::Falling => w.intsel().falling(),
+                InterruptMode::EndOfCompare => w.intsel().eoc(),
+            };
+            match config.filter {
+                Filter::Off => w.flen().off(),
+                Filter::Majority3 => w.flen().maj3(),
+                Filter::Majority5 => w.flen().maj5(),
+            };
+            match config.output {
+                OutputMode::Off => w.out().off(),
+                OutputMode::Async => w.out().async_(),
+                OutputMode::Sync => w.out().sync(),
+            };
+            if config.high_speed {
+                w.speed().high();
+            } else {
+                w.speed().low();
+            }
+            w.hyst().bit(config.hysteresis);
+            w.single().bit(config.single_shot)
+        });
+        self.wait_for_sync();
+
+        self.ac().compctrl[$idx].modify(|_, w| w.enable().set_bit());
+        self.wait_for_sync();
+
+        self.inputs = Some((positive, negative));
+        previous
+    }
+
+    /// Disable the comparator.
+    /// Returns the inputs that were in use, if any.
+    pub fn disable(&mut self) -> Option<(AcPin, NegativeInput)> {
+        self.ac().compctrl[$idx].modify(|_, w| w.enable().clear_bit());
+        self.wait_for_sync();
+        self.inputs.take()
+    }
+
+    /// Route the comparator output to `pin`.  The `output` field of
+    /// the `Config` passed to `enable` selects whether the pin is driven.
+    /// Returns the pin that was previously routed, if any.
+    pub fn route_output(&mut self, pin: $Output) -> Option<$Output> {
+        self.output.replace(pin)
+    }
+
+    /// Stop tracking the output pin and return it.
+    pub fn release_output(&mut self) -> Option<$Output> {
+        self.output.take()
+    }
+
+    /// Start a single shot comparison.  This is only meaningful when
+    /// the comparator was configured with `single_shot` set.
+    pub fn start(&mut self) {
+        self.ac().ctrlb.write(|w| w.$start().set_bit());
+    }
+
+    /// Returns true when the comparator output is valid
+    pub fn is_ready(&self) -> bool {
+        self.ac().statusb.read().$ready().bit_is_set()
+    }
+
+    /// Returns the current comparator output; true when the positive
+    /// input is greater than the negative input.
+    pub fn state(&self) -> bool {
+        self.ac().statusa.read().$state().bit_is_set()
+    }
+
+    /// Enable the interrupt generation for this comparator.
+    /// This method only sets the AC configuration to trigger
+    /// the interrupt; it does not configure the interrupt controller
+    /// or define an interrupt handler.
+    pub fn enable_interrupt(&mut self) {
+        self.ac().intenset.write(|w| w.$comp().set_bit());
+    }
+
+    /// Disables interrupt generation for this comparator.
+    pub fn disable_interrupt(&mut self) {
+        self.ac().intenclr.write(|w| w.$comp().set_bit());
+    }
+
+    /// Returns true if the condition selected by `InterruptMode`
+    /// has occurred, clearing the flag.
+    pub fn triggered(&mut self) -> bool {
+        if self.ac().intflag.read().$comp().bit_is_set() {
+            // Writing a 1 clears the flag
+            self.ac().intflag.write(|w| w.$comp().set_bit());
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Enable or disable generation of an event when the condition
+    /// selected by `InterruptMode` occurs.
+    pub fn event_output(&mut self, enable: bool) {
+        self.ac().evctrl.modify(|_, w| w.$compeo().bit(enable));
+    }
+
+    /// Enable or disable starting a single shot comparison in
+    /// response to an incoming event.
+    pub fn event_input(&mut self, enable: bool) {
+        self.ac().evctrl.modify(|_, w| w.$compei().bit(enable));
+    }
+}
+        )+
+    };
+}
+
+comparator! {
+    Comparator0: (Comparator0Output, 0, state0, ready0, start0, comp0,
+        compeo0, compei0, [Pa12, Pa18]),
+    Comparator1: (Comparator1Output, 1, state1, ready1, start1, comp1,
+        compeo1, compei1, [Pa13, Pa19]),
+}
@@ -0,0 +1,540 @@
+//! Working with the Analog to Digital Converter.
+//! The ADC hardware is exposed through the `Adc` type, which implements
+//! the embedded_hal `OneShot` trait for pins that have been switched
+//! into their analog peripheral function (function B).
+//! In addition to single conversions, the ADC can be placed into
+//! free running mode where results are collected into a ring buffer
+//! from the ADC interrupt handler; see `Adc::into_free_running`.
+use calibration;
+use clock;
+use hal::adc::{Channel, OneShot};
+use nb;
+use target_device::{ADC, PM, SYSCTRL};
+
+#[cfg(feature = "samd21g18a")]
+use gpio::{Pb2, Pb3, Pb8, Pb9};
+use gpio::{Pa10, Pa11, Pa2, Pa3, Pa4, Pa5, Pa6, Pa7, Pa8, Pa9, PfB};
+
+/// The voltage reference used for conversions.
+pub type Reference = target_device::adc::refctrl::REFSELW;
+/// The gain applied to the input prior to conversion.
+pub type Gain = target_device::adc::inputctrl::GAINW;
+/// The resolution of the conversion result.
+pub type Resolution = target_device::adc::ctrlb::RESSELW;
+/// The number of samples that are accumulated and averaged into
+/// a single result.
+pub type SampleRate = target_device::adc::avgctrl::SAMPLENUMW;
+/// The comparison performed by the window monitor.
+pub type WindowMode = target_device::adc::winctrl::WINMODEW;
+
+/// The maximum frequency at which the ADC can be clocked after
+/// the prescaler has been applied.
+const MAX_ADC_FREQ: u32 = 2_100_000;
+
+/// `Adc` encapsulates the ADC peripheral.
+/// The default configuration uses the 1/2 VDDANA reference together with
+/// a gain of 1/2, which yields conversions that span the full 0V to VDDANA
+/// input range with 12 bits of resolution.
+pub struct Adc<ADC> {
+    adc: ADC,
+}
+
+impl Adc<ADC> {
+    /// Power up and configure the ADC.
+    /// The clock is obtained from the `GenericClockController` instance;
+    /// the prescaler is chosen such that the ADC is clocked at no more
+    /// than the 2.1Mhz permitted by the hardware.
+    /// The factory calibration values are loaded from the NVM software
+    /// calibration area.
+    pub fn new(clock: &clock::AdcClock, adc: ADC, pm: &mut PM) -> Self {
+        // this is safe because we're constrained to just the adc bit
+        pm.apbcmask.modify(|_, w| w.adc_().set_bit());
+
+        adc.ctrla.write(|w| w.swrst().set_bit());
+        while adc.ctrla.read().swrst().bit_is_set()
+            || adc.status.read().syncbusy().bit_is_set()
+        {}
+
+        adc.calib.write(|w| unsafe {
+            w.linearity_cal().bits(calibration::adc_linearity_cal());
+            w.bias_cal().bits(calibration::adc_bias_cal())
+        });
+
+        let divider = (clock.freq().0 + MAX_ADC_FREQ - 1) / MAX_ADC_FREQ;
+        adc.ctrlb.write(|w| {
+            match divider.next_power_of_two() {
+                1 | 2 | 4 => w.prescaler().div4(),
+                8 => w.prescaler().div8(),
+                16 => w.prescaler().div16(),
+                32 => w.prescaler().div32(),
+                64 => w.prescaler().div64(),
+                128 => w.prescaler().div128(),
+                256 => w.prescaler().div256(),
+                _ => w.prescaler().div512(),
+            };
+            w.ressel()._12bit()
+        });
+        while adc.status.read().syncbusy().bit_is_set() {}
+
+        // Sampling time is (samplen + 1) * (CLK_ADC / 2); a few cycles is
+        // sufficient for low impedance sources
+        adc.sampctrl.write(|w| unsafe { w.samplen().bits(5) });
+        while adc.status.read().syncbusy().bit_is_set() {}
+
+        adc.inputctrl.write(|w| {
+            w.muxneg().gnd();
+            w.gain().div2()
+        });
+        while adc.status.read().syncbusy().bit_is_set() {}
+
+        adc.refctrl.write(|w| w.refsel().intvcc1());
+
+        let mut adc = Self { adc };
+        adc.samples(SampleRate::_1);
+        adc
+    }
+
+    /// Release the ADC peripheral.
+    /// The ADC is disabled but no other de-initialization is performed.
+    pub fn free(self) -> ADC {
+        self.power_down();
+        self.adc
+    }
+
+    /// Configure whether the ADC keeps converting in standby sleep
+    /// mode, allowing free running conversions to wake the device.
+    /// The ADC clock generator must also run in standby.
+    pub fn run_in_standby(&mut self, enabled: bool) {
+        self.adc.ctrla.modify(|_, w| w.runstdby().bit(enabled));
+        self.wait_for_sync();
+    }
+
+    /// Select the voltage reference used for subsequent conversions.
+    pub fn reference(&mut self, reference: Reference) {
+        self.adc.refctrl.modify(|_, w| w.refsel().variant(reference));
+    }
+
+    /// Select the gain applied to the input for subsequent conversions.
+    pub fn gain(&mut self, gain: Gain) {
+        self.adc.inputctrl.modify(|_, w| w.gain().variant(gain));
+        self.wait_for_sync();
+    }
+
+    /// Select the resolution of the conversion result.
+    /// Note that averaging multiple samples implicitly selects the
+    /// 16 bit accumulation mode; use `samples(SampleRate::_1)` to
+    /// return to single sample mode before changing the resolution.
+    pub fn resolution(&mut self, resolution: Resolution) {
+        self.adc.ctrlb.modify(|_, w| w.ressel().variant(resolution));
+        self.wait_for_sync();
+    }
+
+    /// Configure the number of samples that are accumulated and averaged
+    /// for each conversion result.  When more than one sample is taken,
+    /// the result is right-shifted such that it is reported with 12 bits
+    /// of resolution: up to 16 samples the sum is divided by the number
+    /// of samples, and beyond that the hardware first shifts the sum
+    /// down to 16 bits before the same division by 16.
+    pub fn samples(&mut self, samples: SampleRate) {
+        let multi_sample = match samples {
+            SampleRate::_1 => false,
+            _ => true,
+        };
+        // Table 33-3 in the datasheet: the averaged result needs to be
+        // divided by the number of samples, up to a maximum of 16.
+        // Sums of more than 16 samples are wider than 16 bits, and are
+        // automatically shifted right by the hardware to fit, so
+        // dividing by 16 still yields a 12 bit result.
+        let adjres = match samples {
+            SampleRate::_1 => 0,
+            SampleRate::_2 => 1,
+            SampleRate::_4 => 2,
+            SampleRate::_8 => 3,
+            _ => 4,
+        };
+        self.adc.avgctrl.write(|w| {
+            w.samplenum().variant(samples);
+            unsafe { w.adjres().bits(adjres) }
+        });
+        self.wait_for_sync();
+
+        if multi_sample {
+            self.adc.ctrlb.modify(|_, w| w.ressel()._16bit());
+        } else {
+            self.adc.ctrlb.modify(|_, w| w.ressel()._12bit());
+        }
+        self.wait_for_sync();
+    }
+
+    /// Configure the window monitor.
+    /// The monitor compares each conversion result against the `lower`
+    /// and `upper` thresholds according to `mode`; a result that matches
+    /// raises the window monitor flag.  Use `WindowMode::DISABLE` to turn
+    /// the monitor off again.
+    pub fn window(&mut self, mode: WindowMode, lower: u16, upper: u16) {
+        self.adc.winlt.write(|w| unsafe { w.winlt().bits(lower) });
+        self.wait_for_sync();
+        self.adc.winut.write(|w| unsafe { w.winut().bits(upper) });
+        self.wait_for_sync();
+        self.adc.winctrl.write(|w| w.winmode().variant(mode));
+        self.wait_for_sync();
+    }
+
+    /// Returns true if a conversion result has matched the window
+    /// monitor condition since the last time this method was called.
+    pub fn window_triggered(&mut self) -> bool {
+        if self.adc.intflag.read().winmon().bit_is_set() {
+            // Writing a 1 clears the flag
+            self.adc.intflag.write(|w| w.winmon().set_bit());
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Enables the internal temperature sensor and returns a channel
+    /// that can be passed to `read` to sample it.
+    pub fn temperature_sensor(&mut self, sysctrl: &mut SYSCTRL) -> TemperatureSensor {
+        sysctrl.vref.modify(|_, w| w.tsen().set_bit());
+        TemperatureSensor { _0: () }
+    }
+
+    /// Enables the internal bandgap reference output and returns a
+    /// channel that can be passed to `read` to sample it.
+    pub fn bandgap(&mut self, sysctrl: &mut SYSCTRL) -> Bandgap {
+        sysctrl.vref.modify(|_, w| w.bgouten().set_bit());
+        Bandgap { _0: () }
+    }
+
+    /// Returns a channel that samples the core supply voltage,
+    /// scaled down by a factor of 4.
+    pub fn scaled_core_vcc(&mut self) -> ScaledCoreVcc {
+        ScaledCoreVcc { _0: () }
+    }
+
+    /// Returns a channel that samples the IO supply voltage,
+    /// scaled down by a factor of 4.
+    pub fn scaled_io_vcc(&mut self) -> ScaledIoVcc {
+        ScaledIoVcc { _0: () }
+    }
+
+    /// Perform a differential conversion between the pins in `pair`.
+    /// The result is a signed value that is positive when the voltage
+    /// on the positive input exceeds that of the negative input.
+    pub fn read_differential<P, N>(&mut self, _pair: &mut Differential<P, N>) -> i16
+    where
+        P: Channel<ADC, ID = u8>,
+        N: NegativeChannel,
+    {
+        self.adc.inputctrl.modify(|_, w| unsafe {
+            w.muxpos().bits(P::channel());
+            w.muxneg().bits(N::negative_channel())
+        });
+        self.wait_for_sync();
+        self.adc.ctrlb.modify(|_, w| w.diffmode().set_bit());
+        self.wait_for_sync();
+
+        self.power_up();
+        self.convert();
+        let result = self.convert();
+        self.power_down();
+
+        self.adc.ctrlb.modify(|_, w| w.diffmode().clear_bit());
+        self.wait_for_sync();
+        self.adc.inputctrl.modify(|_, w| w.muxneg().gnd());
+        self.wait_for_sync();
+
+        // In differential mode the result is sign extended to 16 bits
+        result as i16
+    }
+
+    /// Switch the ADC into free running mode, continuously converting
+    /// the specified channel.  Conversion results are collected into
+    /// `buffer` by calling `FreeRunning::service_interrupt` from the ADC
+    /// interrupt handler.
+    pub fn into_free_running<PIN>(self, _pin: &mut PIN, buffer: &'static mut [u16]) -> FreeRunning
+    where
+        PIN: Channel<ADC, ID = u8>,
+    {
+        self.adc
+            .inputctrl
+            .modify(|_, w| unsafe { w.muxpos().bits(PIN::channel()) });
+        self.wait_for_sync();
+        self.adc.ctrlb.modify(|_, w| w.freerun().set_bit());
+        self.wait_for_sync();
+
+        let mut free_running = FreeRunning {
+            adc: self,
+            buffer,
+            head: 0,
+            len: 0,
+        };
+        free_running.adc.power_up();
+        free_running.adc.adc.swtrig.write(|w| w.start().set_bit());
+        free_running
+    }
+
+    fn wait_for_sync(&self) {
+        while self.adc.status.read().syncbusy().bit_is_set() {}
+    }
+
+    fn power_up(&mut self) {
+        self.adc.ctrla.modify(|_, w| w.enable().set_bit());
+        self.wait_for_sync();
+    }
+
+    fn power_down(&self) {
+        self.adc.ctrla.modify(|_, w| w.enable().clear_bit());
+        self.wait_for_sync();
+    }
+
+    fn convert(&mut self) -> u16 {
+        self.adc.swtrig.modify(|_, w| w.start().set_bit());
+        while self.adc.intflag.read().resrdy().bit_is_clear() {}
+        // Writing a 1 clears the flag
+        self.adc.intflag.write(|w| w.resrdy().set_bit());
+        self.adc.result.read().result().bits()
+    }
+
+    fn read_channel(&mut self, channel: u8) -> u16 {
+        self.adc
+            .inputctrl
+            .modify(|_, w| unsafe { w.muxpos().bits(channel) });
+        self.wait_for_sync();
+
+        self.power_up();
+        // The first conversion after changing the input or the
+        // reference must be discarded
+        self.convert();
+        let result = self.convert();
+        self.power_down();
+
+        result
+    }
+}
+
+impl<WORD, PIN> OneShot<ADC, WORD, PIN> for Adc<ADC>
+where
+    WORD: From<u16>,
+    PIN: Channel<ADC, ID = u8>,
+{
+    type Error = ();
+
+    fn read(&mut self, _pin: &mut PIN) -> nb::Result<WORD, Self::Error> {
+        let result = self.read_channel(PIN::channel());
+        Ok(result.into())
+    }
+}
+
+/// The ADC configured to continuously convert a single channel.
+/// Each completed conversion raises the RESRDY interrupt; the interrupt
+/// handler is expected to call `service_interrupt`, which moves the
+/// result into the ring buffer supplied to `Adc::into_free_running`.
+/// When the buffer is full the oldest sample is overwritten.
+pub struct FreeRunning {
+    adc: Adc<ADC>,
+    buffer: &'static mut [u16],
+    head: usize,
+    len: usize,
+}
+
+impl FreeRunning {
+    /// Enable the result ready and window monitor interrupts.
+    /// This method only sets the ADC configuration to trigger the
+    /// interrupt; it does not configure the interrupt controller
+    /// or define an interrupt handler.
+    pub fn enable_interrupt(&mut self) {
+        self.adc.adc.intenset.write(|w| {
+            w.resrdy().set_bit();
+            w.winmon().set_bit()
+        });
+    }
+
+    /// Disables the result ready and window monitor interrupts.
+    pub fn disable_interrupt(&mut self) {
+        self.adc.adc.intenclr.write(|w| {
+            w.resrdy().set_bit();
+            w.winmon().set_bit()
+        });
+    }
+
+    /// Move a completed conversion result into the ring buffer.
+    /// This is intended to be called from the ADC interrupt handler.
+    /// Returns true if the result matched the window monitor
+    /// condition configured via `Adc::window`.
+    pub fn service_interrupt(&mut self) -> bool {
+        let intflag = self.adc.adc.intflag.read();
+        if intflag.resrdy().bit_is_set() {
+            // Reading the result clears the RESRDY flag
+            let sample = self.adc.adc.result.read().result().bits();
+            self.push(sample);
+        }
+        if intflag.overrun().bit_is_set() {
+            // Writing a 1 clears the flag
+            self.adc.adc.intflag.write(|w| w.overrun().set_bit());
+        }
+        self.adc.window_triggered()
+    }
+
+    /// Remove and return the oldest sample from the ring buffer,
+    /// or `None` if no samples are available.
+    pub fn read(&mut self) -> Option<u16> {
+        if self.len == 0 {
+            return None;
+        }
+        let capacity = self.buffer.len();
+        let tail = (self.head + capacity - self.len) % capacity;
+        self.len -= 1;
+        Some(self.buffer[tail])
+    }
+
+    /// Returns the number of samples held in the ring buffer.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the ring buffer holds no samples.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Stop free running conversions and yield the ADC and the
+    /// ring buffer.
+    pub fn stop(mut self) -> (Adc<ADC>, &'static mut [u16]) {
+        self.disable_interrupt();
+        self.adc.power_down();
+        self.adc.adc.ctrlb.modify(|_, w| w.freerun().clear_bit());
+        self.adc.wait_for_sync();
+        (self.adc, self.buffer)
+    }
+
+    fn push(&mut self, sample: u16) {
+        let capacity = self.buffer.len();
+        if capacity == 0 {
+            return;
+        }
+        self.buffer[self.head] = sample;
+        self.head = (self.head + 1) % capacity;
+        if self.len < capacity {
+            self.len += 1;
+        }
+    }
+}
+
+/// The pins that can be routed to the negative input of the ADC
+/// when performing differential conversions.  You should not
+/// implement this trait for yourself.
+pub trait NegativeChannel {
+    fn negative_channel() -> u8;
+}
+
+/// A pair of analog pins used for differential conversions.
+/// Only AIN0 through AIN7 may be used as the negative input.
+pub struct Differential<P, N> {
+    positive: P,
+    negative: N,
+}
+
+impl<P, N> Differential<P, N>
+where
+    P: Channel<ADC, ID = u8>,
+    N: NegativeChannel,
+{
+    /// Pair up the positive and negative input pins
+    pub fn new(positive: P, negative: N) -> Self {
+        Self { positive, negative }
+    }
+
+    /// Break the pair back into its constituent pins
+    pub fn free(self) -> (P, N) {
+        (self.positive, self.negative)
+    }
+}
+
+macro_rules! internal_channels {
+    ($($Type:ident: ($chan:expr, $doc:expr),)+) => {
+        $(
+#[doc = $doc]
+pub struct $Type {
+    _0: (),
+}
+
+impl Channel<ADC> for $Type {
+    type ID = u8;
+
+    fn channel() -> u8 {
+        $chan
+    }
+}
+        )+
+    }
+}
+
+internal_channels! {
+    TemperatureSensor: (0x18, "The internal temperature sensor"),
+    Bandgap: (0x19, "The internal bandgap voltage reference"),
+    ScaledCoreVcc: (0x1a, "The core supply voltage, scaled by 1/4"),
+    ScaledIoVcc: (0x1b, "The IO supply voltage, scaled by 1/4"),
+}
+
+macro_rules! negative_pins {
+    ($($pin:ident: $chan:expr),+) => {
+        $(
+impl NegativeChannel for $pin<PfB> {
+    fn negative_channel() -> u8 {
+        $chan
+    }
+}
+        )+
+    }
+}
+
+negative_pins! {
+    Pa2: 0,
+    Pa3: 1,
+    Pa4: 4,
+    Pa5: 5,
+    Pa6: 6,
+    Pa7: 7
+}
+
+#[cfg(feature = "samd21g18a")]
+negative_pins! {
+    Pb8: 2,
+    Pb9: 3
+}
+
+macro_rules! adc_pins {
+    ($($pin:ident: $chan:expr),+) => {
+        $(
+impl Channel<ADC> for $pin<PfB> {
+    type ID = u8;
+
+    fn channel() -> u8 {
+        $chan
+    }
+}
+        )+
+    }
+}
+
+adc_pins! {
+    Pa2: 0,
+    Pa3: 1,
+    Pa4: 4,
+    Pa5: 5,
+    Pa6: 6,
+    Pa7: 7,
+    Pa8: 16,
+    Pa9: 17,
+    Pa10: 18,
+    Pa11: 19
+}
+
+#[cfg(feature = "samd21g18a")]
+adc_pins! {
+    Pb8: 2,
+    Pb9: 3,
+    Pb2: 10,
+    Pb3: 11
+}
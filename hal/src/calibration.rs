@@ -41,6 +41,19 @@ pub fn dfll48m_coarse_cal() -> u8 {
     cal_with_errata(4, 26, 0x3f, 0x3f, 0x1f) as u8
 }
 
+/// Returns the ADC linearity calibration value.
+/// The value straddles the first two words of the calibration area.
+pub fn adc_linearity_cal() -> u8 {
+    let lsb = cal(0, 27, 0x1f);
+    let msb = cal(4, 0, 0x7);
+    ((msb << 5) | lsb) as u8
+}
+
+/// Returns the ADC bias calibration value
+pub fn adc_bias_cal() -> u8 {
+    cal(4, 3, 0x7) as u8
+}
+
 #[cfg(feature = "usb")]
 /// USB TRANSN calibration value. Should be written to USB PADCAL register.
 pub fn usb_transn_cal() -> u8 {
@@ -3,8 +3,17 @@
 //! before you can set up most of the peripherals on the atsamd21 device.
 //! The other types in this module are used to enforce at compile time
 //! that the peripherals have been correctly configured.
+use core::{cmp, ptr};
+use cortex_m::interrupt;
+use gpio::{
+    Floating, Input, Pa10, Pa11, Pa14, Pa15, Pa16, Pa17, Pa20, Pa21, Pa22, Pa23, Pa27, Pa28, Pa30,
+    PfH,
+};
+#[cfg(feature = "samd21g18a")]
+use gpio::{Pb10, Pb11, Pb22, Pb23};
 use target_device::gclk::clkctrl::GENR::*;
 use target_device::gclk::clkctrl::IDR::*;
+use target_device::gclk::genctrl;
 use target_device::gclk::genctrl::SRCR::*;
 use target_device::{self, GCLK, NVMCTRL, PM, SYSCTRL};
 use time::{Hertz, U32Ext};
@@ -12,6 +21,8 @@ use time::{Hertz, U32Ext};
 pub type ClockId = target_device::gclk::clkctrl::IDR;
 pub type ClockGenId = target_device::gclk::clkctrl::GENR;
 pub type ClockSource = target_device::gclk::genctrl::SRCR;
+/// The amplifier gain of the external crystal oscillator
+pub type XoscGain = target_device::sysctrl::xosc::GAINW;
 
 /// Represents a configured clock generator.
 /// Can be converted into the effective clock frequency.
@@ -30,10 +41,147 @@ impl Into<Hertz> for GClock {
     }
 }
 
+/// The reference clock of the fractional digital PLL
+#[derive(Clone, Copy)]
+pub enum DpllReference {
+    /// The external 32.768Khz crystal oscillator, as enabled by
+    /// `GenericClockController::with_external_32kosc`
+    Xosc32k,
+    /// The external oscillator, as enabled by
+    /// `GenericClockController::enable_xosc`, divided by
+    /// `2 * (divider + 1)`
+    Xosc { divider: u16 },
+    /// A configured clock generator
+    Gclk(GClock),
+}
+
+/// The errors returned when configuring the fractional digital PLL
+#[derive(Debug)]
+pub enum DpllError {
+    /// The DPLL or the clock generator has already been configured
+    AlreadyConfigured,
+    /// The reference oscillator isn't running
+    ReferenceDisabled,
+    /// The reference frequency is outside the 32Khz to 2Mhz range
+    ReferenceOutOfRange(Hertz),
+    /// The loop divider ratio doesn't fit the LDR and LDRFRAC fields,
+    /// or the output frequency is outside the 48Mhz to 96Mhz range
+    OutputOutOfRange(Hertz),
+}
+
+/// The error returned when the external oscillator can't be enabled.
+#[derive(Debug)]
+pub enum XoscError {
+    /// The oscillator has already been enabled
+    AlreadyEnabled,
+    /// The frequency is outside the 0.4Mhz to 32Mhz range
+    FrequencyOutOfRange(Hertz),
+}
+
+/// The signal driving the external oscillator, together with the
+/// XIN (PA14) and XOUT (PA15) pins that it uses.  The pins are
+/// consumed as they are dedicated to the oscillator.
+pub enum XoscMode {
+    /// A crystal connected between XIN and XOUT.  The amplifier `gain`
+    /// must suit the crystal frequency: `_0` up to 2Mhz, `_1` up to
+    /// 4Mhz, `_2` up to 8Mhz, `_3` up to 16Mhz and `_4` up to 30Mhz.
+    /// When `amplitude_control` is set, the gain is reduced
+    /// automatically once the oscillator is running, saving power.
+    Crystal {
+        gain: XoscGain,
+        amplitude_control: bool,
+        xin: Pa14<Input<Floating>>,
+        xout: Pa15<Input<Floating>>,
+    },
+    /// An external clock signal driven on XIN.  XOUT remains
+    /// available as a general purpose pin.
+    External { xin: Pa14<Input<Floating>> },
+}
+
+/// A pin configured for peripheral function H, which connects it to
+/// the GCLK_IO signal of a clock generator.
+pub trait GclkIo {
+    /// Returns the clock generator connected to this pin
+    fn gclk(&self) -> ClockGenId;
+}
+
+/// A clock generator driving the clock signal on its GCLK_IO pin, as
+/// returned by `GenericClockController::enable_gclk_output`.
+pub struct GclkOutput<P> {
+    gclk: ClockGenId,
+    pin: P,
+}
+
+impl<P> GclkOutput<P> {
+    /// Stop driving the clock signal and return the pin.  The clock
+    /// generator keeps running.
+    pub fn disable(self) -> P {
+        // this is safe because only the output enable of the
+        // generator driving our pin is modified
+        let regs = unsafe { &*GCLK::ptr() };
+        modify_genctrl(regs, self.gclk, |w| w.oe().clear_bit());
+        self.pin
+    }
+}
+
+macro_rules! gclk_io {
+    ($($(#[$attr:meta])* $PinType:ident: $gclk:ident,)+) => {
+        $(
+$(#[$attr])*
+impl GclkIo for $PinType<PfH> {
+    fn gclk(&self) -> ClockGenId {
+        $gclk
+    }
+}
+        )+
+    };
+}
+
+gclk_io!(
+    Pa10: GCLK4,
+    Pa11: GCLK5,
+    Pa14: GCLK0,
+    Pa15: GCLK1,
+    Pa16: GCLK2,
+    Pa17: GCLK3,
+    Pa20: GCLK4,
+    Pa21: GCLK5,
+    Pa22: GCLK6,
+    Pa23: GCLK7,
+    Pa27: GCLK0,
+    Pa28: GCLK0,
+    Pa30: GCLK0,
+    #[cfg(feature = "samd21g18a")]
+    Pb10: GCLK4,
+    #[cfg(feature = "samd21g18a")]
+    Pb11: GCLK5,
+    #[cfg(feature = "samd21g18a")]
+    Pb22: GCLK0,
+    #[cfg(feature = "samd21g18a")]
+    Pb23: GCLK1,
+);
+
 struct State {
     gclk: GCLK,
 }
 
+/// Modify the GENCTRL settings of one clock generator.  GENCTRL is
+/// accessed indirectly, so interrupts are disabled to prevent another
+/// access from selecting a different generator part way through.
+fn modify_genctrl<F>(regs: &target_device::gclk::RegisterBlock, gclk: ClockGenId, f: F)
+where
+    F: FnOnce(&mut genctrl::W) -> &mut genctrl::W,
+{
+    interrupt::free(|_| {
+        // GENCTRL is read by first writing the generator ID to its low byte
+        unsafe {
+            ptr::write_volatile(&regs.genctrl as *const _ as *mut u8, gclk.bits());
+        }
+        regs.genctrl.modify(|_, w| f(w));
+        while regs.status.read().syncbusy().bit_is_set() {}
+    });
+}
+
 impl State {
     fn reset_gclk(&mut self) {
         self.gclk.ctrl.write(|w| w.swrst().set_bit());
@@ -52,6 +200,7 @@ impl State {
         divider: u16,
         src: ClockSource,
         improve_duty_cycle: bool,
+        exponential: bool,
     ) {
         self.gclk.gendiv.write(|w| unsafe {
             w.id().bits(gclk.bits());
@@ -62,14 +211,32 @@ impl State {
         self.gclk.genctrl.write(|w| unsafe {
             w.id().bits(gclk.bits());
             w.src().bits(src.bits());
-            // divide directly by divider, rather than exponential
-            w.divsel().clear_bit();
+            // divide either directly by divider, or by 2^(divider+1)
+            w.divsel().bit(exponential);
             w.idc().bit(improve_duty_cycle);
             w.genen().set_bit()
         });
         self.wait_for_sync();
     }
 
+    fn modify_gclk<F>(&mut self, gclk: ClockGenId, f: F)
+    where
+        F: FnOnce(&mut genctrl::W) -> &mut genctrl::W,
+    {
+        modify_genctrl(&self.gclk, gclk, f);
+    }
+
+    fn set_gclk_run_standby(&mut self, gclk: ClockGenId, enabled: bool) {
+        self.modify_gclk(gclk, |w| w.runstdby().bit(enabled));
+    }
+
+    fn enable_gclk_output(&mut self, gclk: ClockGenId, idle_high: bool) {
+        self.modify_gclk(gclk, |w| {
+            w.oov().bit(idle_high);
+            w.oe().set_bit()
+        });
+    }
+
     fn enable_clock_generator(&mut self, clock: ClockId, generator: ClockGenId) {
         self.gclk.clkctrl.write(|w| unsafe {
             w.id().bits(clock.bits());
@@ -90,6 +257,9 @@ pub struct GenericClockController {
     state: State,
     gclks: [Hertz; 8],
     used_clocks: u64,
+    dpll_freq: Hertz,
+    xosc_freq: Hertz,
+    gclk_in: [Hertz; 8],
 }
 
 impl GenericClockController {
@@ -136,9 +306,9 @@ impl GenericClockController {
 
         // Enable a 32khz source -> GCLK1
         if use_external_crystal {
-            state.set_gclk_divider_and_source(GCLK1, 1, XOSC32K, false);
+            state.set_gclk_divider_and_source(GCLK1, 1, XOSC32K, false, false);
         } else {
-            state.set_gclk_divider_and_source(GCLK1, 1, OSC32K, false);
+            state.set_gclk_divider_and_source(GCLK1, 1, OSC32K, false, false);
         }
 
         // Feed 32khz into the DFLL48
@@ -146,7 +316,7 @@ impl GenericClockController {
         // Enable the DFLL48
         configure_and_enable_dfll48m(sysctrl, use_external_crystal);
         // Feed DFLL48 into the main clock
-        state.set_gclk_divider_and_source(GCLK0, 1, DFLL48M, true);
+        state.set_gclk_divider_and_source(GCLK0, 1, DFLL48M, true, false);
         // We are now running at 48Mhz
 
         // Reset various dividers back to 1
@@ -172,6 +342,9 @@ impl GenericClockController {
                 Hertz(0),
             ],
             used_clocks: 1u64 << DFLL48M.bits(),
+            dpll_freq: Hertz(0),
+            xosc_freq: Hertz(0),
+            gclk_in: [Hertz(0); 8],
         }
     }
 
@@ -209,40 +382,285 @@ impl GenericClockController {
     /// Configures a clock generator with the specified divider and
     /// source.
     /// `divider` is a linear divider to be applied to the clock
-    /// source.  While the hardware also supports an exponential divider,
-    /// this function doesn't expose that functionality at this time.
+    /// source; a divider of 0 leaves the clock undivided.  Use
+    /// `configure_gclk_exponential_divider_and_source` for larger
+    /// division factors.
     /// `improve_duty_cycle` is a boolean that, when set to true, enables
     /// a 5o/50 duty cycle for odd divider values.
     /// Returns a `GClock` for the configured clock generator.
-    /// Returns `None` if the clock generator has already been configured.
+    /// Returns `None` if the clock generator has already been configured,
+    /// or if `src` is a reserved source value.
     pub fn configure_gclk_divider_and_source(
         &mut self,
         gclk: ClockGenId,
         divider: u16,
         src: ClockSource,
         improve_duty_cycle: bool,
+    ) -> Option<GClock> {
+        self.configure_gclk(gclk, divider, false, src, improve_duty_cycle)
+    }
+
+    /// Configures a clock generator to divide the specified source by
+    /// `2^(exponent+1)`.  This reaches division factors beyond the range
+    /// of the linear divider, which is 8 bits wide for most generators.
+    /// Returns a `GClock` for the configured clock generator.
+    /// Returns `None` if the clock generator has already been configured,
+    /// or if `src` is a reserved source value.
+    pub fn configure_gclk_exponential_divider_and_source(
+        &mut self,
+        gclk: ClockGenId,
+        exponent: u16,
+        src: ClockSource,
+    ) -> Option<GClock> {
+        self.configure_gclk(gclk, exponent, true, src, false)
+    }
+
+    fn configure_gclk(
+        &mut self,
+        gclk: ClockGenId,
+        divider: u16,
+        exponential: bool,
+        src: ClockSource,
+        improve_duty_cycle: bool,
     ) -> Option<GClock> {
         let idx = gclk.bits() as usize;
         if self.gclks[idx].0 != 0 {
             return None;
         }
-        self.state
-            .set_gclk_divider_and_source(gclk, divider, src, improve_duty_cycle);
         let freq: Hertz = match src {
             XOSC32K | OSC32K | OSCULP32K => OSC32K_FREQ,
             GCLKGEN1 => self.gclks[1],
             OSC8M => 8.mhz().into(),
             DFLL48M => OSC48M_FREQ,
-            DPLL96M => 96.mhz().into(),
-            GCLKIN | XOSC | _ => unimplemented!(),
+            DPLL96M => self.dpll_freq,
+            XOSC => self.xosc_freq,
+            GCLKIN => self.gclk_in[idx],
+            _ => return None,
         };
-        self.gclks[idx] = Hertz(freq.0 / divider as u32);
-        Some(GClock { gclk, freq })
+        self.state
+            .set_gclk_divider_and_source(gclk, divider, src, improve_duty_cycle, exponential);
+        self.gclks[idx] = if exponential {
+            Hertz(freq.0.checked_shr(divider as u32 + 1).unwrap_or(0))
+        } else {
+            Hertz(freq.0 / cmp::max(divider, 1) as u32)
+        };
+        Some(GClock {
+            gclk,
+            freq: self.gclks[idx],
+        })
+    }
+
+    /// Enable the external oscillator, driven at `freq` by either a
+    /// crystal or an external clock signal.  `startup` selects a start
+    /// up time of `2^startup` cycles of the 32Khz ultra low power
+    /// oscillator, up to 15; crystals typically need at least 8.
+    /// The oscillator can then be used as the `XOSC` source of clock
+    /// generators, or as the reference of the DPLL.
+    /// Returns the mode, and so its pins, together with the error if
+    /// the oscillator has already been enabled or `freq` is outside
+    /// the 0.4Mhz to 32Mhz range.
+    pub fn enable_xosc(
+        &mut self,
+        sysctrl: &mut SYSCTRL,
+        freq: Hertz,
+        mode: XoscMode,
+        startup: u8,
+    ) -> Result<Hertz, (XoscMode, XoscError)> {
+        if self.xosc_freq.0 != 0 {
+            return Err((mode, XoscError::AlreadyEnabled));
+        }
+        if freq.0 < 400_000 || freq.0 > 32_000_000 {
+            return Err((mode, XoscError::FrequencyOutOfRange(freq)));
+        }
+        let amplitude_control = match mode {
+            XoscMode::Crystal {
+                amplitude_control, ..
+            } => amplitude_control,
+            XoscMode::External { .. } => false,
+        };
+        sysctrl.xosc.write(|w| {
+            unsafe {
+                w.startup().bits(startup);
+            }
+            w.ondemand().clear_bit();
+            match mode {
+                XoscMode::Crystal { gain, .. } => {
+                    w.gain().variant(gain);
+                    w.xtalen().set_bit()
+                }
+                XoscMode::External { .. } => w.xtalen().clear_bit(),
+            };
+            w.enable().set_bit()
+        });
+        while sysctrl.pclksr.read().xoscrdy().bit_is_clear() {
+            // Wait for the oscillator to stabilize
+        }
+        // The automatic gain control must only be enabled once the
+        // oscillator is stable
+        if amplitude_control {
+            sysctrl.xosc.modify(|_, w| w.ampgc().set_bit());
+        }
+        self.xosc_freq = freq;
+        Ok(freq)
+    }
+
+    /// Configures a clock generator to use the clock signal at `freq`
+    /// on its GCLK_IO pin as its source, divided by `divider`.
+    /// The pin is consumed as it is dedicated to the clock signal.
+    /// Returns a `GClock` for the configured clock generator.
+    /// Returns `None` if the clock generator has already been configured.
+    pub fn configure_gclk_input<P: GclkIo>(
+        &mut self,
+        pin: P,
+        freq: Hertz,
+        divider: u16,
+    ) -> Option<GClock> {
+        let gclk = pin.gclk();
+        let idx = gclk.bits() as usize;
+        if self.gclks[idx].0 != 0 {
+            return None;
+        }
+        self.gclk_in[idx] = freq;
+        self.configure_gclk_divider_and_source(gclk, divider, GCLKIN, false)
+    }
+
+    /// Enable the fractional digital PLL and configure a clock
+    /// generator to use it as its source, with the specified linear
+    /// divider.
+    /// The PLL multiplies the frequency of `reference` by
+    /// `ldr + 1 + ldrfrac / 16`; the reference must be between 32Khz
+    /// and 2Mhz and the resulting frequency between 48Mhz and 96Mhz.
+    /// This blocks until the PLL has locked.
+    /// Returns a `GClock` for the configured clock generator.
+    pub fn configure_dpll96m(
+        &mut self,
+        sysctrl: &mut SYSCTRL,
+        gclk: ClockGenId,
+        divider: u16,
+        reference: DpllReference,
+        ldr: u16,
+        ldrfrac: u8,
+    ) -> Result<GClock, DpllError> {
+        if self.dpll_freq.0 != 0 || self.gclks[gclk.bits() as usize].0 != 0 {
+            return Err(DpllError::AlreadyConfigured);
+        }
+        let ref_freq = match reference {
+            DpllReference::Xosc32k => {
+                if sysctrl.xosc32k.read().enable().bit_is_clear() {
+                    return Err(DpllError::ReferenceDisabled);
+                }
+                XOSC32K_FREQ
+            }
+            DpllReference::Xosc { divider } => {
+                if self.xosc_freq.0 == 0 {
+                    return Err(DpllError::ReferenceDisabled);
+                }
+                Hertz(self.xosc_freq.0 / (2 * (divider as u32 + 1)))
+            }
+            DpllReference::Gclk(generator) => generator.freq,
+        };
+        if ref_freq.0 < 32_000 || ref_freq.0 > 2_000_000 {
+            return Err(DpllError::ReferenceOutOfRange(ref_freq));
+        }
+        let freq =
+            Hertz(((ref_freq.0 as u64 * (16 * (ldr as u64 + 1) + ldrfrac as u64)) / 16) as u32);
+        if ldr > 0xfff || ldrfrac > 0xf || freq.0 < 48_000_000 || freq.0 > 96_000_000 {
+            return Err(DpllError::OutputOutOfRange(freq));
+        }
+
+        if let DpllReference::Gclk(generator) = reference {
+            self.used_clocks |= 1u64 << FDPLL.bits();
+            self.state.enable_clock_generator(FDPLL, generator.gclk);
+        }
+        configure_and_enable_dpll96m(sysctrl, reference, ldr, ldrfrac);
+        self.dpll_freq = freq;
+
+        Ok(self
+            .configure_gclk_divider_and_source(gclk, divider, DPLL96M, false)
+            .unwrap())
+    }
+
+    /// Output a configured clock generator on its GCLK_IO pin, for
+    /// example to clock an external device.  `idle_high` selects the
+    /// level of the pin while the generator is disabled.
+    /// The pin is held by the returned `GclkOutput` until the output
+    /// is disabled, as it is dedicated to the clock signal.
+    /// Returns the pin as an error if it isn't connected to the
+    /// generator.
+    pub fn enable_gclk_output<P: GclkIo>(
+        &mut self,
+        generator: &GClock,
+        pin: P,
+        idle_high: bool,
+    ) -> Result<GclkOutput<P>, P> {
+        if pin.gclk() != generator.gclk {
+            return Err(pin);
+        }
+        self.state.enable_gclk_output(generator.gclk, idle_high);
+        Ok(GclkOutput {
+            gclk: generator.gclk,
+            pin,
+        })
+    }
+
+    /// Configure whether a clock generator keeps running in standby
+    /// sleep mode.  Peripherals that run in standby need their clock
+    /// generator, and its source, to do so as well.
+    pub fn configure_standby(&mut self, gclk: ClockGenId, run_in_standby: bool) {
+        self.state.set_gclk_run_standby(gclk, run_in_standby);
+    }
+
+    /// Configure the standby behavior of a clock source.  When
+    /// `run_in_standby` is set the source keeps running in standby
+    /// sleep mode.  When `on_demand` is set the source only runs while
+    /// a clock generator or peripheral requests it, saving power when
+    /// its consumers are idle.
+    /// The always-on OSCULP32K and the generator inputs have no such
+    /// options, so `src` is returned as an error for them.
+    pub fn configure_source_standby(
+        &mut self,
+        sysctrl: &mut SYSCTRL,
+        src: ClockSource,
+        run_in_standby: bool,
+        on_demand: bool,
+    ) -> Result<(), ClockSource> {
+        match src {
+            XOSC => sysctrl.xosc.modify(|_, w| {
+                w.runstdby().bit(run_in_standby);
+                w.ondemand().bit(on_demand)
+            }),
+            XOSC32K => sysctrl.xosc32k.modify(|_, w| {
+                w.runstdby().bit(run_in_standby);
+                w.ondemand().bit(on_demand)
+            }),
+            OSC32K => sysctrl.osc32k.modify(|_, w| {
+                w.runstdby().bit(run_in_standby);
+                w.ondemand().bit(on_demand)
+            }),
+            OSC8M => sysctrl.osc8m.modify(|_, w| {
+                w.runstdby().bit(run_in_standby);
+                w.ondemand().bit(on_demand)
+            }),
+            DFLL48M => {
+                wait_for_dfllrdy(sysctrl);
+                sysctrl.dfllctrl.modify(|_, w| {
+                    w.runstdby().bit(run_in_standby);
+                    w.ondemand().bit(on_demand)
+                });
+                wait_for_dfllrdy(sysctrl);
+            }
+            DPLL96M => sysctrl.dpllctrla.modify(|_, w| {
+                w.runstdby().bit(run_in_standby);
+                w.ondemand().bit(on_demand)
+            }),
+            _ => return Err(src),
+        }
+        Ok(())
     }
 }
 
 macro_rules! clock_generator {
-    ($(($id:ident, $Type:ident, $clock:ident),)+) => {
+    ($(($id:ident, $Type:ident, $clock:expr),)+) => {
 
 $(
 /// A typed token that indicates that the clock for the peripheral(s)
@@ -300,21 +718,54 @@ impl GenericClockController {
     }
 }
 
+// One token per peripheral channel of the CLKCTRL.ID field, listed in
+// ID order so that it can be checked against the PAC's `IDW` variants;
+// a macro can't enumerate those itself.
+// The DFLL48 and FDPLL channels are configured by the controller
+// itself, as the references of those oscillators.
 clock_generator!(
-    (tcc2_tc3, Tcc2Tc3Clock, TCC2_TC3),
-    (tc4_tc5, Tc4Tc5Clock, TC4_TC5),
-    (tc6_tc7, Tc6Tc7Clock, TC6_TC7),
+    (fdpll32k, Fdpll32kClock, FDPLL32K),
+    (wdt, WdtClock, WDT),
+    (rtc, RtcClock, RTC),
+    (eic, EicClock, EIC),
+    (usb, UsbClock, USB),
+    (evsys0, Evsys0Clock, EVSYS_0),
+    (evsys1, Evsys1Clock, EVSYS_1),
+    (evsys2, Evsys2Clock, EVSYS_2),
+    (evsys3, Evsys3Clock, EVSYS_3),
+    (evsys4, Evsys4Clock, EVSYS_4),
+    (evsys5, Evsys5Clock, EVSYS_5),
+    (evsys6, Evsys6Clock, EVSYS_6),
+    (evsys7, Evsys7Clock, EVSYS_7),
+    (evsys8, Evsys8Clock, EVSYS_8),
+    (evsys9, Evsys9Clock, EVSYS_9),
+    (evsys10, Evsys10Clock, EVSYS_10),
+    (evsys11, Evsys11Clock, EVSYS_11),
+    (sercomx_slow, SercomxSlowClock, SERCOMX_SLOW),
     (sercom0_core, Sercom0CoreClock, SERCOM0_CORE),
     (sercom1_core, Sercom1CoreClock, SERCOM1_CORE),
     (sercom2_core, Sercom2CoreClock, SERCOM2_CORE),
     (sercom3_core, Sercom3CoreClock, SERCOM3_CORE),
     (sercom4_core, Sercom4CoreClock, SERCOM4_CORE),
     (sercom5_core, Sercom5CoreClock, SERCOM5_CORE),
-    (usb, UsbClock, USB),
+    (tcc0_tcc1, Tcc0Tcc1Clock, TCC0_TCC1),
+    (tcc2_tc3, Tcc2Tc3Clock, TCC2_TC3),
+    (tc4_tc5, Tc4Tc5Clock, TC4_TC5),
+    (tc6_tc7, Tc6Tc7Clock, TC6_TC7),
+    (adc, AdcClock, ADC),
+    (ac_dig, AcDigClock, AC_DIG),
+    (ac_ana, AcAnaClock, AC_ANA),
+    (dac, DacClock, DAC),
+    // The SVD omits the PTC channel, ID 0x22 in the datasheet, so it
+    // only exists as a reserved value of the PAC's ID enums
+    (ptc, PtcClock, _Reserved(34)),
+    (i2s0, I2s0Clock, I2S_0),
+    (i2s1, I2s1Clock, I2S_1),
 );
 
 /// Helper type for computing effective frequency given a source
 /// clock frequency and a desired frequency.
+#[deprecated(note = "use `CounterParams`, which reports frequencies that can't be reached")]
 #[derive(Debug, Clone, Copy)]
 pub struct ClockParams {
     /// The frequency of the source/input clock
@@ -327,35 +778,147 @@ pub struct ClockParams {
     pub effective_freq: Hertz,
 }
 
+#[allow(deprecated)]
 impl ClockParams {
     /// Given a source frequency and a desired frequency, compute the
     /// `ClockParams` values for the closest matching clock configuration.
+    /// Frequencies outside the range of the prescalers are clamped to
+    /// the smallest or largest division.
     pub fn new(src_freq: Hertz, desired_freq: Hertz) -> Self {
-        let divider = (src_freq.0 / desired_freq.0.saturating_sub(1).max(1)).next_power_of_two();
-        let divider = match divider {
-            1 | 2 | 4 | 8 | 16 | 64 | 256 | 1024 => divider,
-            // There are a couple of gaps, so we round up to the next largest
-            // divider; we'll need to count twice as many but it will work.
-            32 => 64,
-            128 => 256,
-            512 => 1024,
-            // Catch all case; this is lame.  Would be great to detect this
-            // and fail at compile time.
-            _ => 1024,
+        let divider = match CounterParams::prescaled(src_freq, desired_freq) {
+            Some(params) => params.divider,
+            None if desired_freq.0 > src_freq.0 => COUNTER_PRESCALERS[0],
+            None => COUNTER_PRESCALERS[COUNTER_PRESCALERS.len() - 1],
         };
-        let effective_freq = Hertz(src_freq.0 / divider);
         Self {
             src_freq,
-            divider: divider as u16,
-            effective_freq,
+            divider,
+            effective_freq: Hertz(src_freq.0 / divider as u32),
         }
     }
 }
 
+/// Select the prescaler division given by `CounterParams` on the CTRLA
+/// writer of a TC or TCC.  This is a macro because each counter mode
+/// and peripheral has its own writer type.
+macro_rules! set_prescaler {
+    ($w:expr, $divider:expr) => {
+        match $divider {
+            1 => $w.prescaler().div1(),
+            2 => $w.prescaler().div2(),
+            4 => $w.prescaler().div4(),
+            8 => $w.prescaler().div8(),
+            16 => $w.prescaler().div16(),
+            64 => $w.prescaler().div64(),
+            256 => $w.prescaler().div256(),
+            1024 => $w.prescaler().div1024(),
+            _ => unreachable!(),
+        }
+    };
+}
+
+/// The prescaler divisions supported by the timer counters.
+const COUNTER_PRESCALERS: [u16; 8] = [1, 2, 4, 8, 16, 64, 256, 1024];
+
+/// Helper type for computing the prescaler and TOP value with which
+/// a timer counter most closely generates a desired frequency.
+#[derive(Debug, Clone, Copy)]
+pub struct CounterParams {
+    /// The frequency of the source/input clock
+    pub src_freq: Hertz,
+    /// The prescaler division value
+    pub divider: u16,
+    /// The value at which the counter wraps; each period is
+    /// `top + 1` cycles of the prescaled clock.
+    pub top: u32,
+    /// The effective frequency, which is ideally the desired frequency,
+    /// but is produced by dividing the `src_freq` by the `divider`
+    /// and then by `top + 1`.
+    pub effective_freq: Hertz,
+}
+
+impl CounterParams {
+    /// Given a source frequency and a desired frequency, search all of
+    /// the prescalers for the configuration of a counter that wraps at
+    /// no more than `max_top` with the smallest frequency error.  Of
+    /// configurations that are equally good, the smallest prescaler
+    /// is used.  Returns `None` if the desired frequency is out of
+    /// range.
+    pub fn new(src_freq: Hertz, desired_freq: Hertz, max_top: u32) -> Option<Self> {
+        if desired_freq.0 == 0 {
+            return None;
+        }
+        let desired = desired_freq.0 as u64;
+        let mut best: Option<(u64, Self)> = None;
+        for &divider in COUNTER_PRESCALERS.iter() {
+            let clock = src_freq.0 as u64 / divider as u64;
+            // Round to the nearest whole number of cycles
+            let cycles = (clock + desired / 2) / desired;
+            if cycles == 0 || cycles > max_top as u64 + 1 {
+                continue;
+            }
+            let effective = clock / cycles;
+            let error = if effective > desired {
+                effective - desired
+            } else {
+                desired - effective
+            };
+            if best.map_or(true, |(best_error, _)| error < best_error) {
+                let params = Self {
+                    src_freq,
+                    divider,
+                    top: (cycles - 1) as u32,
+                    effective_freq: Hertz(effective as u32),
+                };
+                best = Some((error, params));
+            }
+        }
+        best.map(|(_, params)| params)
+    }
+
+    /// Given a source frequency and a desired frequency, select the
+    /// prescaler whose output is closest to the desired frequency, for
+    /// a counter that runs freely rather than wrapping at a TOP value.
+    /// Of prescalers that are equally close, the smallest is used.
+    /// Returns `None` if the desired frequency is faster than the source
+    /// or slower than the largest prescaler can produce.
+    pub fn prescaled(src_freq: Hertz, desired_freq: Hertz) -> Option<Self> {
+        let max_divider = COUNTER_PRESCALERS[COUNTER_PRESCALERS.len() - 1];
+        if desired_freq.0 > src_freq.0
+            || (desired_freq.0 as u64) * (max_divider as u64) < src_freq.0 as u64
+        {
+            return None;
+        }
+        let error = |divider: u16| {
+            let clock = src_freq.0 / divider as u32;
+            if clock > desired_freq.0 {
+                clock - desired_freq.0
+            } else {
+                desired_freq.0 - clock
+            }
+        };
+        let mut divider = COUNTER_PRESCALERS[0];
+        for &candidate in COUNTER_PRESCALERS.iter() {
+            if error(candidate) < error(divider) {
+                divider = candidate;
+            }
+        }
+        Some(Self {
+            src_freq,
+            divider,
+            top: 0,
+            effective_freq: Hertz(src_freq.0 / divider as u32),
+        })
+    }
+}
+
 /// The frequency of the 48Mhz source.
 pub const OSC48M_FREQ: Hertz = Hertz(48_000_000);
 /// The frequency of the 32Khz source.
 pub const OSC32K_FREQ: Hertz = Hertz(32_000);
+/// The frequency of the external 32Khz crystal, as used to compute the
+/// output of the fractional digital PLL.
+pub const XOSC32K_FREQ: Hertz = Hertz(32_768);
 
 fn set_flash_to_half_auto_wait_state(nvmctrl: &mut NVMCTRL) {
     nvmctrl.ctrlb.modify(|_, w| w.rws().half());
@@ -406,6 +969,38 @@ fn wait_for_dfllrdy(sysctrl: &mut SYSCTRL) {
     while sysctrl.pclksr.read().dfllrdy().bit_is_clear() {}
 }
 
+/// Configure the dpll96m and wait for it to lock
+fn configure_and_enable_dpll96m(
+    sysctrl: &mut SYSCTRL,
+    reference: DpllReference,
+    ldr: u16,
+    ldrfrac: u8,
+) {
+    sysctrl.dpllctrla.write(|w| w.enable().clear_bit());
+    while sysctrl.dpllstatus.read().enable().bit_is_set() {}
+
+    sysctrl.dpllratio.write(|w| unsafe {
+        w.ldr().bits(ldr);
+        w.ldrfrac().bits(ldrfrac)
+    });
+    sysctrl.dpllctrlb.write(|w| match reference {
+        DpllReference::Xosc32k => w.refclk().ref0(),
+        DpllReference::Xosc { divider, .. } => {
+            unsafe {
+                w.div().bits(divider);
+            }
+            w.refclk().ref1()
+        }
+        DpllReference::Gclk(_) => w.refclk().gclk(),
+    });
+    sysctrl.dpllctrla.write(|w| w.enable().set_bit());
+    while sysctrl.dpllstatus.read().lock().bit_is_clear()
+        || sysctrl.dpllstatus.read().clkrdy().bit_is_clear()
+    {
+        // Wait for the PLL to lock
+    }
+}
+
 /// Configure the dfll48m to operate at 48Mhz
 fn configure_and_enable_dfll48m(sysctrl: &mut SYSCTRL, use_external_crystal: bool) {
     // Turn it off while we configure it.
@@ -479,3 +1074,97 @@ fn configure_and_enable_dfll48m(sysctrl: &mut SYSCTRL, use_external_crystal: boo
 
     wait_for_dfllrdy(sysctrl);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::CounterParams;
+    use time::Hertz;
+
+    #[test]
+    fn exact_fit_uses_smallest_prescaler() {
+        let params = CounterParams::new(Hertz(48_000_000), Hertz(1_000), 0xffff).unwrap();
+        assert_eq!(params.divider, 1);
+        assert_eq!(params.top, 47_999);
+        assert_eq!(params.effective_freq, Hertz(1_000));
+    }
+
+    #[test]
+    fn prescaler_is_raised_until_top_fits() {
+        let params = CounterParams::new(Hertz(48_000_000), Hertz(100), 0xffff).unwrap();
+        assert_eq!(params.divider, 8);
+        assert_eq!(params.top, 59_999);
+        assert_eq!(params.effective_freq, Hertz(100));
+    }
+
+    #[test]
+    fn missing_prescaler_is_skipped() {
+        // /16 needs too many counts and there is no /32
+        let params = CounterParams::new(Hertz(48_000_000), Hertz(20), 0xffff).unwrap();
+        assert_eq!(params.divider, 64);
+        assert_eq!(params.top, 37_499);
+        assert_eq!(params.effective_freq, Hertz(20));
+    }
+
+    #[test]
+    fn count_is_rounded_to_nearest() {
+        let params = CounterParams::new(Hertz(48_000_000), Hertz(7_000), 0xffff).unwrap();
+        assert_eq!(params.divider, 1);
+        assert_eq!(params.top, 6_856);
+        assert_eq!(params.effective_freq, Hertz(7_000));
+    }
+
+    #[test]
+    fn prescaled_clock_without_counting() {
+        let params = CounterParams::new(Hertz(48_000_000), Hertz(750_000), 0).unwrap();
+        assert_eq!(params.divider, 64);
+        assert_eq!(params.top, 0);
+        assert_eq!(params.effective_freq, Hertz(750_000));
+    }
+
+    #[test]
+    fn too_fast() {
+        assert!(CounterParams::new(Hertz(1_000_000), Hertz(3_000_000), 0xffff).is_none());
+    }
+
+    #[test]
+    fn too_slow() {
+        assert!(CounterParams::new(Hertz(48_000_000), Hertz(1), 0xff).is_none());
+    }
+
+    #[test]
+    fn zero_frequency() {
+        assert!(CounterParams::new(Hertz(48_000_000), Hertz(0), 0xffff).is_none());
+    }
+
+    #[test]
+    fn prescaled_exact() {
+        let params = CounterParams::prescaled(Hertz(48_000_000), Hertz(750_000)).unwrap();
+        assert_eq!(params.divider, 64);
+        assert_eq!(params.effective_freq, Hertz(750_000));
+    }
+
+    #[test]
+    fn prescaled_between_prescalers() {
+        // 3Mhz is closer than 750Khz
+        let params = CounterParams::prescaled(Hertz(48_000_000), Hertz(2_000_000)).unwrap();
+        assert_eq!(params.divider, 16);
+        assert_eq!(params.effective_freq, Hertz(3_000_000));
+
+        // 750Khz is closer than 3Mhz
+        let params = CounterParams::prescaled(Hertz(48_000_000), Hertz(1_000_000)).unwrap();
+        assert_eq!(params.divider, 64);
+        assert_eq!(params.effective_freq, Hertz(750_000));
+
+        // 46875Hz is closer than 187.5Khz
+        let params = CounterParams::prescaled(Hertz(48_000_000), Hertz(100_000)).unwrap();
+        assert_eq!(params.divider, 1024);
+        assert_eq!(params.effective_freq, Hertz(46_875));
+    }
+
+    #[test]
+    fn prescaled_out_of_range() {
+        assert!(CounterParams::prescaled(Hertz(48_000_000), Hertz(48_000_001)).is_none());
+        assert!(CounterParams::prescaled(Hertz(48_000_000), Hertz(10_000)).is_none());
+        assert!(CounterParams::prescaled(Hertz(48_000_000), Hertz(0)).is_none());
+    }
+}
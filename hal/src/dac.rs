@@ -0,0 +1,172 @@
+//! Working with the Digital to Analog Converter.
+//! The DAC has a single 10-bit output channel that is routed to
+//! pin PA02 when that pin is configured for peripheral function B.
+//! Values can either be written directly using `Dac::write`, or
+//! streamed from a buffer with each conversion started by an incoming
+//! event, typically generated by a timer via the event system.
+use clock;
+use gpio::{Pa2, PfB};
+use target_device::{DAC, PM};
+
+/// The voltage reference used by the DAC.
+/// `INT1V` is the internal 1.0V reference, `AVCC` is the analog supply
+/// voltage VDDANA and `VREFP` is an external reference on the VREFA pin.
+pub type Reference = target_device::dac::ctrlb::REFSELW;
+
+/// The largest value that can be converted by the 10-bit DAC.
+pub const MAX_VALUE: u16 = 0x3ff;
+
+/// `Dac` represents the DAC peripheral driving the VOUT pin.
+pub struct Dac {
+    dac: DAC,
+    pin: Pa2<PfB>,
+}
+
+impl Dac {
+    /// Power up and configure the DAC to drive `pin` using the
+    /// specified voltage reference.
+    /// The clock is obtained from the `GenericClockController` instance.
+    pub fn new(
+        _clock: &clock::DacClock,
+        dac: DAC,
+        pm: &mut PM,
+        pin: Pa2<PfB>,
+        reference: Reference,
+    ) -> Self {
+        // this is safe because we're constrained to just the dac bit
+        pm.apbcmask.modify(|_, w| w.dac_().set_bit());
+
+        dac.ctrla.write(|w| w.swrst().set_bit());
+        while dac.ctrla.read().swrst().bit_is_set() || dac.status.read().syncbusy().bit_is_set()
+        {}
+
+        dac.ctrlb.write(|w| {
+            w.refsel().variant(reference);
+            // Drive the VOUT pin
+            w.eoen().set_bit()
+        });
+
+        dac.ctrla.modify(|_, w| w.enable().set_bit());
+        while dac.status.read().syncbusy().bit_is_set() {}
+
+        Self { dac, pin }
+    }
+
+    /// Disable the DAC and yield the DAC peripheral and the output pin.
+    pub fn free(self) -> (DAC, Pa2<PfB>) {
+        self.dac.ctrla.modify(|_, w| w.enable().clear_bit());
+        while self.dac.status.read().syncbusy().bit_is_set() {}
+        (self.dac, self.pin)
+    }
+
+    /// Configure whether the DAC keeps converting in standby sleep
+    /// mode.  The DAC clock generator must also run in standby.
+    pub fn run_in_standby(&mut self, enabled: bool) {
+        let was_enabled = self.dac.ctrla.read().enable().bit_is_set();
+        self.dac.ctrla.modify(|_, w| w.enable().clear_bit());
+        while self.dac.status.read().syncbusy().bit_is_set() {}
+        self.dac.ctrla.modify(|_, w| {
+            w.runstdby().bit(enabled);
+            w.enable().bit(was_enabled)
+        });
+        while self.dac.status.read().syncbusy().bit_is_set() {}
+    }
+
+    /// Convert `value` and drive the result on the output pin.
+    /// Values larger than `MAX_VALUE` are clamped.
+    /// Blocks until the value has been synchronized to the DAC.
+    pub fn write(&mut self, value: u16) {
+        let value = value.min(MAX_VALUE);
+        self.dac.data.write(|w| unsafe { w.data().bits(value) });
+        while self.dac.status.read().syncbusy().bit_is_set() {}
+    }
+
+    /// Switch the DAC into event triggered mode and stream `samples` to
+    /// the output.  Each event received on the DAC START event input
+    /// moves the next sample into the output; the event is typically
+    /// generated by a timer overflow so that the samples are emitted at
+    /// a fixed rate.  The samples are repeated from the start once the
+    /// end of the buffer has been reached, which is convenient for
+    /// generating periodic waveforms.
+    /// `Streaming::service_interrupt` must be called from the DAC
+    /// interrupt handler to refill the data buffer.
+    pub fn into_streaming(self, samples: &'static [u16]) -> Streaming {
+        self.dac.ctrla.modify(|_, w| w.enable().clear_bit());
+        while self.dac.status.read().syncbusy().bit_is_set() {}
+
+        self.dac.evctrl.write(|w| w.startei().set_bit());
+
+        self.dac.ctrla.modify(|_, w| w.enable().set_bit());
+        while self.dac.status.read().syncbusy().bit_is_set() {}
+
+        let mut streaming = Streaming {
+            dac: self,
+            samples,
+            pos: 0,
+        };
+        streaming.service_interrupt();
+        streaming
+    }
+}
+
+/// The DAC configured to stream a buffer of samples, starting each
+/// conversion in response to an event.
+pub struct Streaming {
+    dac: Dac,
+    samples: &'static [u16],
+    pos: usize,
+}
+
+impl Streaming {
+    /// Enable the data buffer empty interrupt.
+    /// This method only sets the DAC configuration to trigger the
+    /// interrupt; it does not configure the interrupt controller
+    /// or define an interrupt handler.
+    pub fn enable_interrupt(&mut self) {
+        self.dac.dac.intenset.write(|w| w.empty().set_bit());
+    }
+
+    /// Disables the data buffer empty interrupt.
+    pub fn disable_interrupt(&mut self) {
+        self.dac.dac.intenclr.write(|w| w.empty().set_bit());
+    }
+
+    /// Load the next sample into the data buffer if it is empty.
+    /// This is intended to be called from the DAC interrupt handler.
+    /// Returns true if the DAC ran out of data since the last call,
+    /// which means that a sample was missed.
+    pub fn service_interrupt(&mut self) -> bool {
+        let intflag = self.dac.dac.intflag.read();
+        let underrun = intflag.underrun().bit_is_set();
+        if underrun {
+            // Writing a 1 clears the flag
+            self.dac.dac.intflag.write(|w| w.underrun().set_bit());
+        }
+
+        if intflag.empty().bit_is_set() && !self.samples.is_empty() {
+            let value = self.samples[self.pos].min(MAX_VALUE);
+            // Writing to the data buffer clears the EMPTY flag
+            self.dac
+                .dac
+                .databuf
+                .write(|w| unsafe { w.databuf().bits(value) });
+            self.pos = (self.pos + 1) % self.samples.len();
+        }
+
+        underrun
+    }
+
+    /// Stop streaming and return to directly driven mode.
+    pub fn stop(mut self) -> Dac {
+        self.disable_interrupt();
+        let dac = self.dac;
+        dac.dac.ctrla.modify(|_, w| w.enable().clear_bit());
+        while dac.dac.status.read().syncbusy().bit_is_set() {}
+
+        dac.dac.evctrl.write(|w| w.startei().clear_bit());
+
+        dac.dac.ctrla.modify(|_, w| w.enable().set_bit());
+        while dac.dac.status.read().syncbusy().bit_is_set() {}
+        dac
+    }
+}
@@ -0,0 +1,716 @@
+//! Working with the Direct Memory Access Controller.
+//! The DMAC moves data between memory and peripherals without involving
+//! the CPU.  `DmaController::new` powers up the hardware, points it at
+//! the descriptor and write-back sections owned by this module and
+//! splits it into its twelve channels.
+//! Each `Channel` can start a single `Transfer` at a time; the transfer
+//! takes ownership of the channel and the `'static` buffers involved,
+//! and hands them back when it completes via `Transfer::wait`.
+//! Methods that fail hand the channel and buffers back as the error,
+//! so that they are never lost.
+use core::marker::PhantomData;
+use core::ptr;
+use core::sync::atomic::{self, Ordering};
+use cortex_m::interrupt;
+use target_device::dmac::RegisterBlock;
+use target_device::{DMAC, PM};
+
+/// The number of DMA channels provided by the hardware
+pub const NUM_CHANNELS: usize = 12;
+
+/// The largest number of beats that a single transfer can move
+pub const MAX_BEATS: usize = 65535;
+
+/// A transfer descriptor, as laid out in SRAM for the DMAC to consume.
+/// See 20.10.1 Transfer Descriptor in the datasheet.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TransferDescriptor {
+    btctrl: u16,
+    btcnt: u16,
+    srcaddr: u32,
+    dstaddr: u32,
+    descaddr: u32,
+}
+
+impl TransferDescriptor {
+    const EMPTY: TransferDescriptor = TransferDescriptor {
+        btctrl: 0,
+        btcnt: 0,
+        srcaddr: 0,
+        dstaddr: 0,
+        descaddr: 0,
+    };
+}
+
+// The descriptor and write-back sections must be 128-bit aligned
+#[repr(C, align(16))]
+struct DescriptorSection([TransferDescriptor; NUM_CHANNELS]);
+
+// These are only ever touched by the owner of the DMAC peripheral,
+// or by the owner of the corresponding `Channel`.
+static mut DESCRIPTOR_SECTION: DescriptorSection =
+    DescriptorSection([TransferDescriptor::EMPTY; NUM_CHANNELS]);
+static mut WRITEBACK_SECTION: DescriptorSection =
+    DescriptorSection([TransferDescriptor::EMPTY; NUM_CHANNELS]);
+
+// BTCTRL bit fields
+const BTCTRL_VALID: u16 = 1 << 0;
+const BTCTRL_BEATSIZE_SHIFT: u16 = 8;
+const BTCTRL_SRCINC: u16 = 1 << 10;
+const BTCTRL_DSTINC: u16 = 1 << 11;
+
+/// The size of each beat of a transfer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BeatSize {
+    Byte,
+    HalfWord,
+    Word,
+}
+
+impl BeatSize {
+    fn bits(self) -> u16 {
+        match self {
+            BeatSize::Byte => 0,
+            BeatSize::HalfWord => 1,
+            BeatSize::Word => 2,
+        }
+    }
+
+    fn bytes(self) -> u32 {
+        match self {
+            BeatSize::Byte => 1,
+            BeatSize::HalfWord => 2,
+            BeatSize::Word => 4,
+        }
+    }
+}
+
+/// The types that can be moved by the DMAC in a single beat.
+/// You should not implement this trait for yourself.
+pub trait Beat: Copy {
+    const SIZE: BeatSize;
+}
+
+impl Beat for u8 {
+    const SIZE: BeatSize = BeatSize::Byte;
+}
+
+impl Beat for u16 {
+    const SIZE: BeatSize = BeatSize::HalfWord;
+}
+
+impl Beat for u32 {
+    const SIZE: BeatSize = BeatSize::Word;
+}
+
+/// The arbitration priority level of a channel.
+/// Channels at higher levels are serviced before those at lower levels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Priority {
+    Level0,
+    Level1,
+    Level2,
+    Level3,
+}
+
+/// The amount of data transferred each time the trigger fires
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TriggerAction {
+    /// Each trigger transfers one beat
+    Beat,
+    /// Each trigger transfers the whole block
+    Block,
+    /// Each trigger transfers the whole transaction
+    Transaction,
+}
+
+/// The peripheral events that can trigger a DMA transfer.
+/// See 20.8.19 CHCTRLB in the datasheet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TriggerSource {
+    /// Only software triggers are used; the transfer proceeds as
+    /// quickly as the bus allows
+    Disable,
+    Sercom0Rx,
+    Sercom0Tx,
+    Sercom1Rx,
+    Sercom1Tx,
+    Sercom2Rx,
+    Sercom2Tx,
+    Sercom3Rx,
+    Sercom3Tx,
+    Sercom4Rx,
+    Sercom4Tx,
+    Sercom5Rx,
+    Sercom5Tx,
+    Tcc0Ovf,
+    Tcc0Mc0,
+    Tcc0Mc1,
+    Tcc0Mc2,
+    Tcc0Mc3,
+    Tcc1Ovf,
+    Tcc1Mc0,
+    Tcc1Mc1,
+    Tcc2Ovf,
+    Tcc2Mc0,
+    Tcc2Mc1,
+    Tc3Ovf,
+    Tc3Mc0,
+    Tc3Mc1,
+    Tc4Ovf,
+    Tc4Mc0,
+    Tc4Mc1,
+    Tc5Ovf,
+    Tc5Mc0,
+    Tc5Mc1,
+    AdcResrdy,
+    DacEmpty,
+}
+
+impl TriggerSource {
+    fn bits(self) -> u8 {
+        use self::TriggerSource::*;
+        match self {
+            Disable => 0x00,
+            Sercom0Rx => 0x01,
+            Sercom0Tx => 0x02,
+            Sercom1Rx => 0x03,
+            Sercom1Tx => 0x04,
+            Sercom2Rx => 0x05,
+            Sercom2Tx => 0x06,
+            Sercom3Rx => 0x07,
+            Sercom3Tx => 0x08,
+            Sercom4Rx => 0x09,
+            Sercom4Tx => 0x0a,
+            Sercom5Rx => 0x0b,
+            Sercom5Tx => 0x0c,
+            Tcc0Ovf => 0x0d,
+            Tcc0Mc0 => 0x0e,
+            Tcc0Mc1 => 0x0f,
+            Tcc0Mc2 => 0x10,
+            Tcc0Mc3 => 0x11,
+            Tcc1Ovf => 0x12,
+            Tcc1Mc0 => 0x13,
+            Tcc1Mc1 => 0x14,
+            Tcc2Ovf => 0x15,
+            Tcc2Mc0 => 0x16,
+            Tcc2Mc1 => 0x17,
+            Tc3Ovf => 0x18,
+            Tc3Mc0 => 0x19,
+            Tc3Mc1 => 0x1a,
+            Tc4Ovf => 0x1b,
+            Tc4Mc0 => 0x1c,
+            Tc4Mc1 => 0x1d,
+            Tc5Ovf => 0x1e,
+            Tc5Mc0 => 0x1f,
+            Tc5Mc1 => 0x20,
+            AdcResrdy => 0x27,
+            DacEmpty => 0x28,
+        }
+    }
+}
+
+/// The complete configuration of a single block transfer.
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    pub beat_size: BeatSize,
+    /// Increment the source address after each beat
+    pub src_increment: bool,
+    /// Increment the destination address after each beat
+    pub dst_increment: bool,
+    pub trigger: TriggerSource,
+    pub trigger_action: TriggerAction,
+    pub priority: Priority,
+}
+
+/// `DmaController` owns the DMAC peripheral and its descriptor memory.
+pub struct DmaController {
+    dmac: DMAC,
+}
+
+impl DmaController {
+    /// Power up and reset the DMAC, and split it into its channels.
+    /// All four priority levels are enabled.
+    pub fn new(dmac: DMAC, pm: &mut PM) -> (Self, Channels) {
+        // this is safe because we're constrained to just the dmac bits
+        pm.ahbmask.modify(|_, w| w.dmac_().set_bit());
+        pm.apbbmask.modify(|_, w| w.dmac_().set_bit());
+
+        dmac.ctrl.modify(|_, w| {
+            w.dmaenable().clear_bit();
+            w.crcenable().clear_bit()
+        });
+        dmac.ctrl.write(|w| w.swrst().set_bit());
+        while dmac.ctrl.read().swrst().bit_is_set() {}
+
+        unsafe {
+            dmac.baseaddr
+                .write(|w| w.baseaddr().bits(&DESCRIPTOR_SECTION as *const _ as u32));
+            dmac.wrbaddr
+                .write(|w| w.wrbaddr().bits(&WRITEBACK_SECTION as *const _ as u32));
+        }
+
+        dmac.ctrl.write(|w| {
+            w.dmaenable().set_bit();
+            w.lvlen0().set_bit();
+            w.lvlen1().set_bit();
+            w.lvlen2().set_bit();
+            w.lvlen3().set_bit()
+        });
+
+        (Self { dmac }, Channels::new())
+    }
+
+    /// Disable the DMAC and release the peripheral.
+    /// All of the channels must be returned to ensure that no
+    /// transfers are in progress.
+    pub fn free(self, _channels: Channels) -> DMAC {
+        self.dmac.ctrl.modify(|_, w| w.dmaenable().clear_bit());
+        self.dmac
+    }
+}
+
+/// This is a helper trait that identifies the hardware channel
+/// number of a `Channel`.  You should not implement this trait
+/// for yourself.
+pub trait ChannelId {
+    const ID: u8;
+}
+
+/// A single DMA channel.  The `CH` type identifies the hardware channel.
+pub struct Channel<CH> {
+    _ch: PhantomData<CH>,
+}
+
+impl<CH> Channel<CH>
+where
+    CH: ChannelId,
+{
+    fn dmac(&self) -> &RegisterBlock {
+        // This is safe because the channel registers are only accessed
+        // via the CHID window inside a critical section, and each
+        // `Channel` is the sole owner of its channel number.
+        unsafe { &*DMAC::ptr() }
+    }
+
+    /// Run `f` with the channel registers windowed onto this channel
+    fn with_channel<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&RegisterBlock) -> R,
+    {
+        let dmac = self.dmac();
+        interrupt::free(|_| {
+            dmac.chid.write(|w| unsafe { w.id().bits(CH::ID) });
+            f(dmac)
+        })
+    }
+
+    /// Enable the interrupt generation for this channel.  The interrupt
+    /// is raised when a transfer completes or fails.
+    /// This method only sets the DMAC configuration to trigger
+    /// the interrupt; it does not configure the interrupt controller
+    /// or define an interrupt handler.
+    pub fn enable_interrupt(&mut self) {
+        self.with_channel(|dmac| {
+            dmac.chintenset.write(|w| {
+                w.tcmpl().set_bit();
+                w.terr().set_bit()
+            })
+        });
+    }
+
+    /// Disables interrupt generation for this channel.
+    pub fn disable_interrupt(&mut self) {
+        self.with_channel(|dmac| {
+            dmac.chintenclr.write(|w| {
+                w.tcmpl().set_bit();
+                w.terr().set_bit()
+            })
+        });
+    }
+
+    /// Copy `src` into `dst` as quickly as the bus allows.
+    /// The number of beats transferred is the length of the shorter
+    /// of the two buffers.
+    /// Returns the channel and the buffers as an error if more than
+    /// `MAX_BEATS` beats would be transferred.
+    pub fn copy<T>(
+        self,
+        src: &'static [T],
+        dst: &'static mut [T],
+        priority: Priority,
+    ) -> Result<
+        Transfer<CH, (&'static [T], &'static mut [T])>,
+        (Channel<CH>, (&'static [T], &'static mut [T])),
+    >
+    where
+        T: Beat,
+    {
+        let count = src.len().min(dst.len());
+        let config = Config {
+            beat_size: T::SIZE,
+            src_increment: true,
+            dst_increment: true,
+            trigger: TriggerSource::Disable,
+            trigger_action: TriggerAction::Transaction,
+            priority,
+        };
+        unsafe {
+            self.start(
+                config,
+                src.as_ptr() as u32,
+                dst.as_mut_ptr() as u32,
+                count,
+                (src, dst),
+            )
+        }
+    }
+
+    /// Write the contents of `src` to the peripheral register at `dst`,
+    /// transferring one beat each time `trigger` fires.
+    /// Returns the channel and the buffer as an error if `src` is longer
+    /// than `MAX_BEATS`.
+    /// This is unsafe because `dst` must be the address of a peripheral
+    /// data register that can accept the writes.
+    pub unsafe fn write_to<T>(
+        self,
+        src: &'static [T],
+        dst: *mut T,
+        trigger: TriggerSource,
+        priority: Priority,
+    ) -> Result<Transfer<CH, &'static [T]>, (Channel<CH>, &'static [T])>
+    where
+        T: Beat,
+    {
+        let config = Config {
+            beat_size: T::SIZE,
+            src_increment: true,
+            dst_increment: false,
+            trigger,
+            trigger_action: TriggerAction::Beat,
+            priority,
+        };
+        self.start(config, src.as_ptr() as u32, dst as u32, src.len(), src)
+    }
+
+    /// Fill `dst` from the peripheral register at `src`, transferring
+    /// one beat each time `trigger` fires.
+    /// Returns the channel and the buffer as an error if `dst` is longer
+    /// than `MAX_BEATS`.
+    /// This is unsafe because `src` must be the address of a peripheral
+    /// data register that can be read.
+    pub unsafe fn read_from<T>(
+        self,
+        src: *const T,
+        dst: &'static mut [T],
+        trigger: TriggerSource,
+        priority: Priority,
+    ) -> Result<Transfer<CH, &'static mut [T]>, (Channel<CH>, &'static mut [T])>
+    where
+        T: Beat,
+    {
+        let config = Config {
+            beat_size: T::SIZE,
+            src_increment: false,
+            dst_increment: true,
+            trigger,
+            trigger_action: TriggerAction::Beat,
+            priority,
+        };
+        let len = dst.len();
+        self.start(config, src as u32, dst.as_mut_ptr() as u32, len, dst)
+    }
+
+    /// Fill `dst` from the peripheral register at `src`, transferring
+    /// one beat each time `trigger` fires.  Once `dst` has been filled
+    /// the transfer wraps around to the start of the buffer and continues
+    /// indefinitely, until stopped via `CircularTransfer::stop`.
+    /// Returns the channel and the buffer as an error if `dst` is empty
+    /// or longer than `MAX_BEATS`.
+    /// This is unsafe because `src` must be the address of a peripheral
+    /// data register that can be read.
+    pub unsafe fn read_from_circular<T>(
+        self,
+        src: *const T,
+        dst: &'static mut [T],
+        trigger: TriggerSource,
+        priority: Priority,
+    ) -> Result<CircularTransfer<CH, &'static mut [T]>, (Channel<CH>, &'static mut [T])>
+    where
+        T: Beat,
+    {
+        let config = Config {
+            beat_size: T::SIZE,
+            src_increment: false,
+            dst_increment: true,
+            trigger,
+            trigger_action: TriggerAction::Beat,
+            priority,
+        };
+        let len = dst.len();
+        if len == 0 || len > MAX_BEATS {
+            return Err((self, dst));
+        }
+        self.launch(&config, src as u32, dst.as_mut_ptr() as u32, len, true);
+        Ok(CircularTransfer {
+            channel: self,
+            payload: dst,
+            count: len as u16,
+        })
+    }
+
+    /// Start a transfer of `count` beats from `src` to `dst`, using the
+    /// supplied configuration.  `payload` is held by the returned
+    /// `Transfer` until it completes.
+    /// This is unsafe because the caller must guarantee that `src` and
+    /// `dst` remain valid for the duration of the transfer; typically
+    /// this is achieved by making them part of `payload`.
+    /// A `count` of zero produces a transfer that has already completed.
+    /// Returns the channel and the payload as an error if `count` is
+    /// larger than `MAX_BEATS`, the number of beats that the hardware
+    /// can transfer in a single block.
+    pub unsafe fn start<P>(
+        self,
+        config: Config,
+        src: u32,
+        dst: u32,
+        count: usize,
+        payload: P,
+    ) -> Result<Transfer<CH, P>, (Channel<CH>, P)> {
+        if count > MAX_BEATS {
+            return Err((self, payload));
+        }
+        if count != 0 {
+            self.launch(&config, src, dst, count, false);
+        }
+        Ok(Transfer {
+            channel: self,
+            payload,
+            empty: count == 0,
+        })
+    }
+
+    /// Program the descriptor for this channel and enable it.
+    /// A circular descriptor links back to itself so that the block
+    /// is repeated until the channel is disabled.
+    unsafe fn launch(&self, config: &Config, src: u32, dst: u32, count: usize, circular: bool) {
+        let beatsize = config.beat_size.bits();
+        let bytes = config.beat_size.bytes() * count as u32;
+
+        // When the address is incremented, the descriptor holds the
+        // address just past the end of the block
+        let mut btctrl = BTCTRL_VALID | (beatsize << BTCTRL_BEATSIZE_SHIFT);
+        let srcaddr = if config.src_increment {
+            btctrl |= BTCTRL_SRCINC;
+            src + bytes
+        } else {
+            src
+        };
+        let dstaddr = if config.dst_increment {
+            btctrl |= BTCTRL_DSTINC;
+            dst + bytes
+        } else {
+            dst
+        };
+
+        self.with_channel(|dmac| {
+            dmac.chctrla.modify(|_, w| w.enable().clear_bit());
+            while dmac.chctrla.read().enable().bit_is_set() {}
+            dmac.chctrla.write(|w| w.swrst().set_bit());
+            while dmac.chctrla.read().swrst().bit_is_set() {}
+
+            dmac.chctrlb.write(|w| {
+                w.lvl().bits(match config.priority {
+                    Priority::Level0 => 0,
+                    Priority::Level1 => 1,
+                    Priority::Level2 => 2,
+                    Priority::Level3 => 3,
+                });
+                w.trigsrc().bits(config.trigger.bits());
+                match config.trigger_action {
+                    TriggerAction::Beat => w.trigact().beat(),
+                    TriggerAction::Block => w.trigact().block(),
+                    TriggerAction::Transaction => w.trigact().transaction(),
+                }
+            });
+        });
+
+        let descriptor = &mut DESCRIPTOR_SECTION.0[CH::ID as usize];
+        let descaddr = if circular {
+            descriptor as *const _ as u32
+        } else {
+            0
+        };
+        let descriptor_value = TransferDescriptor {
+            btctrl,
+            btcnt: count as u16,
+            srcaddr,
+            dstaddr,
+            descaddr,
+        };
+        ptr::write_volatile(descriptor, descriptor_value);
+        // The write-back entry is only updated once the DMAC has started
+        // working on the channel; seed it so that progress can be
+        // reported from the outset
+        ptr::write_volatile(&mut WRITEBACK_SECTION.0[CH::ID as usize], descriptor_value);
+        // Ensure that the descriptor and the source buffer have been
+        // written out before the DMAC starts reading them
+        atomic::compiler_fence(Ordering::SeqCst);
+
+        self.with_channel(|dmac| dmac.chctrla.modify(|_, w| w.enable().set_bit()));
+
+        if config.trigger == TriggerSource::Disable {
+            self.dmac().swtrigctrl.write(|w| w.bits(1 << CH::ID as u32));
+        }
+    }
+
+    /// Disable the channel and clear its interrupt flags
+    fn disable(&self) {
+        self.with_channel(|dmac| {
+            dmac.chctrla.modify(|_, w| w.enable().clear_bit());
+            while dmac.chctrla.read().enable().bit_is_set() {}
+            // Writing a 1 clears the flags
+            dmac.chintflag.write(|w| {
+                w.tcmpl().set_bit();
+                w.terr().set_bit();
+                w.susp().set_bit()
+            });
+        });
+        // Ensure that the payload is not read before the DMAC has
+        // finished writing to it
+        atomic::compiler_fence(Ordering::SeqCst);
+    }
+}
+
+/// An in-progress DMA transfer.  The channel and the payload are
+/// returned once the transfer has completed.
+pub struct Transfer<CH, P> {
+    channel: Channel<CH>,
+    payload: P,
+    empty: bool,
+}
+
+impl<CH, P> Transfer<CH, P>
+where
+    CH: ChannelId,
+{
+    /// Returns true once the transfer has completed, either
+    /// successfully or due to a bus error.
+    pub fn is_done(&self) -> bool {
+        if self.empty {
+            return true;
+        }
+        self.channel.with_channel(|dmac| {
+            let flags = dmac.chintflag.read();
+            flags.tcmpl().bit_is_set() || flags.terr().bit_is_set()
+        })
+    }
+
+    /// Block until the transfer has completed, then disable the
+    /// channel and return it together with the payload.
+    /// They are returned as an error if the transfer was aborted by a
+    /// bus error, in which case the payload is only partially transferred.
+    /// This also acknowledges the channel interrupt, so it may be
+    /// called from the DMAC interrupt handler once `is_done` is true.
+    pub fn wait(self) -> Result<(Channel<CH>, P), (Channel<CH>, P)> {
+        if self.empty {
+            return Ok((self.channel, self.payload));
+        }
+        while !self.is_done() {}
+        let failed = self
+            .channel
+            .with_channel(|dmac| dmac.chintflag.read().terr().bit_is_set());
+        self.channel.disable();
+        if failed {
+            Err((self.channel, self.payload))
+        } else {
+            Ok((self.channel, self.payload))
+        }
+    }
+}
+
+/// A DMA transfer that repeatedly cycles through its buffer.
+/// Created by `Channel::read_from_circular`.
+pub struct CircularTransfer<CH, P> {
+    channel: Channel<CH>,
+    payload: P,
+    count: u16,
+}
+
+impl<CH, P> CircularTransfer<CH, P>
+where
+    CH: ChannelId,
+{
+    /// Returns the index of the next beat to be transferred within
+    /// the buffer.
+    pub fn position(&self) -> usize {
+        let remaining = self.channel.with_channel(|dmac| {
+            let active = dmac.active.read();
+            if active.abusy().bit_is_set() && active.id().bits() == CH::ID {
+                active.btcnt().bits()
+            } else {
+                unsafe { ptr::read_volatile(&WRITEBACK_SECTION.0[CH::ID as usize]).btcnt }
+            }
+        });
+        (self.count - remaining) as usize % self.count as usize
+    }
+
+    /// Returns a reference to the payload.  Note that the DMAC may
+    /// be writing to the payload concurrently.
+    pub fn payload(&self) -> &P {
+        &self.payload
+    }
+
+    /// Stop the transfer and return the channel together with the
+    /// payload.
+    pub fn stop(self) -> (Channel<CH>, P) {
+        self.channel.disable();
+        (self.channel, self.payload)
+    }
+}
+
+macro_rules! channels {
+    ($($CH:ident: ($ch:ident, $id:expr),)+) => {
+        $(
+/// Identifies the hardware DMA channel with the matching number
+pub struct $CH;
+
+impl ChannelId for $CH {
+    const ID: u8 = $id;
+}
+        )+
+
+/// Holds the individual DMA channels
+pub struct Channels {
+    $(
+        pub $ch: Channel<$CH>,
+    )+
+}
+
+impl Channels {
+    fn new() -> Self {
+        Self {
+            $(
+                $ch: Channel { _ch: PhantomData },
+            )+
+        }
+    }
+}
+    }
+}
+
+channels! {
+    Ch0: (ch0, 0),
+    Ch1: (ch1, 1),
+    Ch2: (ch2, 2),
+    Ch3: (ch3, 3),
+    Ch4: (ch4, 4),
+    Ch5: (ch5, 5),
+    Ch6: (ch6, 6),
+    Ch7: (ch7, 7),
+    Ch8: (ch8, 8),
+    Ch9: (ch9, 9),
+    Ch10: (ch10, 10),
+    Ch11: (ch11, 11),
+}
@@ -0,0 +1,404 @@
+//! Working with the External Interrupt Controller.
+//! The EIC can raise an interrupt, wake the device from sleep or
+//! generate an event when the level on a pin changes.  Each pin that
+//! supports external interrupts is connected to one of sixteen EXTINT
+//! lines, and several pins share each line.  The line is given by the
+//! table of pins at the end of this module; it is usually the pin
+//! number modulo 16, but there are exceptions such as PA24 on line 12
+//! and PA28 on line 8.
+//! `ExternalInterruptController::new` powers up the EIC and returns the
+//! tokens for the individual lines.  A pin is connected to its line by
+//! passing the matching token to the `into_ei` method of the pin,
+//! which means that two pins sharing a line can't both be claimed.
+//! PA08 is instead connected to the dedicated non-maskable interrupt
+//! input, which is claimed via `into_nmi`.
+use clock;
+use core::marker::PhantomData;
+use cortex_m::interrupt;
+use gpio::{Input, PfA, Port};
+use gpio::{
+    Pa0, Pa1, Pa10, Pa11, Pa12, Pa13, Pa14, Pa15, Pa16, Pa17, Pa18, Pa19, Pa2, Pa20, Pa21, Pa22,
+    Pa23, Pa24, Pa25, Pa27, Pa28, Pa3, Pa30, Pa31, Pa4, Pa5, Pa6, Pa7, Pa8, Pa9,
+};
+#[cfg(feature = "samd21g18a")]
+use gpio::{Pb10, Pb11, Pb2, Pb22, Pb23, Pb3, Pb8, Pb9};
+use target_device::eic::RegisterBlock;
+use target_device::{EIC, PM, PORT};
+
+/// The condition on the pin that triggers the interrupt or event
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sense {
+    /// Detection is disabled
+    None,
+    /// Rising edge
+    Rise,
+    /// Falling edge
+    Fall,
+    /// Either edge
+    Both,
+    /// The pin is high
+    High,
+    /// The pin is low
+    Low,
+}
+
+impl Sense {
+    fn bits(self) -> u32 {
+        match self {
+            Sense::None => 0,
+            Sense::Rise => 1,
+            Sense::Fall => 2,
+            Sense::Both => 3,
+            Sense::High => 4,
+            Sense::Low => 5,
+        }
+    }
+}
+
+/// `ExternalInterruptController` owns the EIC peripheral.
+pub struct ExternalInterruptController {
+    eic: EIC,
+}
+
+impl ExternalInterruptController {
+    /// Power up the EIC and return it together with the tokens for
+    /// its external interrupt lines.
+    /// The clock is obtained from the `GenericClockController`; it is
+    /// needed for edge detection and filtering, but not for level
+    /// detection when waking from sleep.
+    pub fn new(_clock: &clock::EicClock, eic: EIC, pm: &mut PM) -> (Self, ExtIntLines) {
+        // this is safe because we're constrained to just the eic bit
+        pm.apbamask.modify(|_, w| w.eic_().set_bit());
+
+        eic.ctrl.write(|w| w.swrst().set_bit());
+        while eic.ctrl.read().swrst().bit_is_set() || eic.status.read().syncbusy().bit_is_set() {}
+
+        eic.ctrl.modify(|_, w| w.enable().set_bit());
+        while eic.status.read().syncbusy().bit_is_set() {}
+
+        (Self { eic }, ExtIntLines::new())
+    }
+
+    /// Disable the EIC and release the EIC peripheral.
+    /// All of the line tokens must be returned so that no pin remains
+    /// connected to a line.
+    pub fn free(self, _lines: ExtIntLines) -> EIC {
+        self.eic.ctrl.modify(|_, w| w.enable().clear_bit());
+        while self.eic.status.read().syncbusy().bit_is_set() {}
+        self.eic
+    }
+}
+
+/// Identifies an external interrupt line.
+/// It doesn't make sense to implement this trait outside of this module.
+pub trait LineId {
+    const ID: u8;
+}
+
+/// The token for an external interrupt line.  The `LINE` type
+/// identifies the hardware line.
+pub struct ExtInt<LINE> {
+    _line: PhantomData<LINE>,
+}
+
+/// A pin connected to its external interrupt line.
+pub struct ExtIntPin<PIN, LINE> {
+    pin: PIN,
+    line: ExtInt<LINE>,
+}
+
+impl<PIN, LINE> ExtIntPin<PIN, LINE>
+where
+    LINE: LineId,
+{
+    fn eic(&self) -> &RegisterBlock {
+        // This is safe because each pin only touches the bits that
+        // belong to its own line, and the registers that are shared
+        // between lines are modified inside a critical section.
+        unsafe { &*EIC::ptr() }
+    }
+
+    fn mask(&self) -> u32 {
+        1 << LINE::ID
+    }
+
+    /// Modify the four bit configuration field for this line
+    fn configure(&mut self, clear: u32, set: u32) {
+        let shift = (LINE::ID as u32 % 8) * 4;
+        let config = &self.eic().config[LINE::ID as usize / 8];
+        interrupt::free(|_| {
+            config.modify(|r, w| unsafe { w.bits((r.bits() & !(clear << shift)) | (set << shift)) })
+        });
+    }
+
+    /// Select the condition on the pin that triggers the interrupt
+    /// or event.
+    pub fn sense(&mut self, sense: Sense) {
+        self.configure(0x7, sense.bits());
+    }
+
+    /// Enable or disable the majority filter, which requires the pin
+    /// to hold its level for three consecutive samples.
+    pub fn filter(&mut self, enable: bool) {
+        self.configure(0x8, if enable { 0x8 } else { 0 });
+    }
+
+    /// Enable the interrupt generation for this line.
+    /// This method only sets the EIC configuration to trigger
+    /// the interrupt; it does not configure the interrupt controller
+    /// or define an interrupt handler.
+    pub fn enable_interrupt(&mut self) {
+        let mask = self.mask();
+        self.eic().intenset.write(|w| unsafe { w.bits(mask) });
+    }
+
+    /// Disables interrupt generation for this line.
+    pub fn disable_interrupt(&mut self) {
+        let mask = self.mask();
+        self.eic().intenclr.write(|w| unsafe { w.bits(mask) });
+    }
+
+    /// Returns true if the condition selected by `sense` has occurred.
+    pub fn is_interrupt(&self) -> bool {
+        self.eic().intflag.read().bits() & self.mask() != 0
+    }
+
+    /// Clear the interrupt flag for this line.
+    pub fn clear_interrupt(&mut self) {
+        let mask = self.mask();
+        // Writing a 1 clears the flag
+        self.eic().intflag.write(|w| unsafe { w.bits(mask) });
+    }
+
+    /// Enable or disable waking the device from sleep when the
+    /// condition selected by `sense` occurs.  The interrupt must also
+    /// be enabled for the device to wake.
+    pub fn wakeup(&mut self, enable: bool) {
+        let mask = self.mask();
+        let wakeup = &self.eic().wakeup;
+        interrupt::free(|_| {
+            wakeup.modify(|r, w| unsafe {
+                w.bits(if enable {
+                    r.bits() | mask
+                } else {
+                    r.bits() & !mask
+                })
+            })
+        });
+    }
+
+    /// Enable or disable generation of an event when the condition
+    /// selected by `sense` occurs.
+    pub fn event_output(&mut self, enable: bool) {
+        let mask = self.mask();
+        let evctrl = &self.eic().evctrl;
+        interrupt::free(|_| {
+            evctrl.modify(|r, w| unsafe {
+                w.bits(if enable {
+                    r.bits() | mask
+                } else {
+                    r.bits() & !mask
+                })
+            })
+        });
+    }
+
+    /// Disconnect the pin from the line, returning the pin and the
+    /// line token.
+    pub fn free(mut self) -> (PIN, ExtInt<LINE>) {
+        self.disable_interrupt();
+        self.wakeup(false);
+        self.event_output(false);
+        self.configure(0xf, 0);
+        self.clear_interrupt();
+        (self.pin, self.line)
+    }
+}
+
+macro_rules! lines {
+    ($($LINE:ident: ($line:ident, $id:expr),)+) => {
+        $(
+/// Identifies the external interrupt line with the matching number
+pub struct $LINE;
+
+impl LineId for $LINE {
+    const ID: u8 = $id;
+}
+        )+
+
+/// Holds the tokens for the individual external interrupt lines
+/// and the non-maskable interrupt input
+pub struct ExtIntLines {
+    $(
+        pub $line: ExtInt<$LINE>,
+    )+
+    pub nmi: NmiLine,
+}
+
+impl ExtIntLines {
+    fn new() -> Self {
+        Self {
+            $(
+                $line: ExtInt { _line: PhantomData },
+            )+
+            nmi: NmiLine { _0: () },
+        }
+    }
+}
+    }
+}
+
+lines! {
+    Line0: (extint0, 0),
+    Line1: (extint1, 1),
+    Line2: (extint2, 2),
+    Line3: (extint3, 3),
+    Line4: (extint4, 4),
+    Line5: (extint5, 5),
+    Line6: (extint6, 6),
+    Line7: (extint7, 7),
+    Line8: (extint8, 8),
+    Line9: (extint9, 9),
+    Line10: (extint10, 10),
+    Line11: (extint11, 11),
+    Line12: (extint12, 12),
+    Line13: (extint13, 13),
+    Line14: (extint14, 14),
+    Line15: (extint15, 15),
+}
+
+/// The token for the non-maskable interrupt input.
+pub struct NmiLine {
+    _0: (),
+}
+
+/// PA08 connected to the non-maskable interrupt input.
+/// The NMI can't be masked or disabled other than by setting the
+/// sense to `Sense::None`; the handler is the `NMI` exception rather
+/// than an interrupt.
+pub struct Nmi {
+    pin: Pa8<PfA>,
+    line: NmiLine,
+}
+
+impl Nmi {
+    fn eic(&self) -> &RegisterBlock {
+        // This is safe because the NMI registers are not shared
+        // with the EXTINT lines.
+        unsafe { &*EIC::ptr() }
+    }
+
+    /// Select the condition on the pin that triggers the NMI.
+    /// Detection starts as soon as the sense is set to anything other
+    /// than `Sense::None`, so the `NMI` exception handler must be
+    /// ready beforehand.
+    pub fn sense(&mut self, sense: Sense) {
+        self.eic()
+            .nmictrl
+            .modify(|_, w| unsafe { w.nmisense().bits(sense.bits() as u8) });
+    }
+
+    /// Enable or disable the majority filter, which requires the pin
+    /// to hold its level for three consecutive samples.
+    pub fn filter(&mut self, enable: bool) {
+        self.eic().nmictrl.modify(|_, w| w.nmifilten().bit(enable));
+    }
+
+    /// Returns true if the condition selected by `sense` has occurred.
+    pub fn is_interrupt(&self) -> bool {
+        self.eic().nmiflag.read().nmi().bit_is_set()
+    }
+
+    /// Clear the NMI flag.  This must be done from the `NMI` exception
+    /// handler when using edge detection.
+    pub fn clear_interrupt(&mut self) {
+        // Writing a 1 clears the flag
+        self.eic().nmiflag.write(|w| w.nmi().set_bit());
+    }
+
+    /// Disconnect the pin from the NMI input, returning the pin and the
+    /// NMI token.
+    pub fn free(mut self) -> (Pa8<PfA>, NmiLine) {
+        self.eic().nmictrl.write(|w| w.nmisense().none());
+        self.clear_interrupt();
+        (self.pin, self.line)
+    }
+}
+
+impl<MODE> Pa8<Input<MODE>> {
+    /// Connect the pin to the non-maskable interrupt input.  The pull
+    /// resistor configuration of the input is retained.
+    pub fn into_nmi(self, port: &mut Port, line: NmiLine) -> Nmi {
+        // This is safe because we only touch the configuration of
+        // this pin, which we own.
+        let pincfg = unsafe { &(*PORT::ptr()).pincfg0_[8] };
+        let pullen = pincfg.read().pullen().bit();
+        let pin = self.into_function_a(port);
+        pincfg.modify(|_, w| w.inen().set_bit().pullen().bit(pullen));
+        Nmi { pin, line }
+    }
+}
+
+macro_rules! ei_pins {
+    ($($Pin:ident: ($Line:ident, $pincfg:ident, $pin_no:expr),)+) => {
+        $(
+impl<MODE> $Pin<Input<MODE>> {
+    /// Connect the pin to its external interrupt line.  The pull
+    /// resistor configuration of the input is retained.
+    pub fn into_ei(self, port: &mut Port, line: ExtInt<$Line>) -> ExtIntPin<$Pin<PfA>, $Line> {
+        // This is safe because we only touch the configuration of
+        // this pin, which we own.
+        let pincfg = unsafe { &(*PORT::ptr()).$pincfg[$pin_no] };
+        let pullen = pincfg.read().pullen().bit();
+        let pin = self.into_function_a(port);
+        pincfg.modify(|_, w| w.inen().set_bit().pullen().bit(pullen));
+        ExtIntPin { pin, line }
+    }
+}
+        )+
+    };
+}
+
+ei_pins! {
+    Pa0: (Line0, pincfg0_, 0),
+    Pa1: (Line1, pincfg0_, 1),
+    Pa2: (Line2, pincfg0_, 2),
+    Pa3: (Line3, pincfg0_, 3),
+    Pa4: (Line4, pincfg0_, 4),
+    Pa5: (Line5, pincfg0_, 5),
+    Pa6: (Line6, pincfg0_, 6),
+    Pa7: (Line7, pincfg0_, 7),
+    Pa9: (Line9, pincfg0_, 9),
+    Pa10: (Line10, pincfg0_, 10),
+    Pa11: (Line11, pincfg0_, 11),
+    Pa12: (Line12, pincfg0_, 12),
+    Pa13: (Line13, pincfg0_, 13),
+    Pa14: (Line14, pincfg0_, 14),
+    Pa15: (Line15, pincfg0_, 15),
+    Pa16: (Line0, pincfg0_, 16),
+    Pa17: (Line1, pincfg0_, 17),
+    Pa18: (Line2, pincfg0_, 18),
+    Pa19: (Line3, pincfg0_, 19),
+    Pa20: (Line4, pincfg0_, 20),
+    Pa21: (Line5, pincfg0_, 21),
+    Pa22: (Line6, pincfg0_, 22),
+    Pa23: (Line7, pincfg0_, 23),
+    Pa24: (Line12, pincfg0_, 24),
+    Pa25: (Line13, pincfg0_, 25),
+    Pa27: (Line15, pincfg0_, 27),
+    Pa28: (Line8, pincfg0_, 28),
+    Pa30: (Line10, pincfg0_, 30),
+    Pa31: (Line11, pincfg0_, 31),
+}
+
+#[cfg(feature = "samd21g18a")]
+ei_pins! {
+    Pb2: (Line2, pincfg1_, 2),
+    Pb3: (Line3, pincfg1_, 3),
+    Pb8: (Line8, pincfg1_, 8),
+    Pb9: (Line9, pincfg1_, 9),
+    Pb10: (Line10, pincfg1_, 10),
+    Pb11: (Line11, pincfg1_, 11),
+    Pb22: (Line6, pincfg1_, 22),
+    Pb23: (Line7, pincfg1_, 23),
+}
@@ -0,0 +1,476 @@
+//! Working with the Event System.
+//! The event system routes events from one peripheral, the generator,
+//! to others, the users, without involving the CPU.  For example, a
+//! timer overflow can start an ADC conversion or move the next sample
+//! into the DAC.
+//! `EventSystem::new` returns the tokens for the twelve channels and
+//! for the event users.  A channel is routed from a `Generator`, and
+//! each user is then connected to a routed channel.  As the tokens are
+//! consumed while in use, a user can't be connected to two channels at
+//! once, and a channel can't be freed while users remain connected.
+use clock;
+use core::marker::PhantomData;
+use core::ptr;
+use cortex_m::interrupt;
+use target_device::evsys::RegisterBlock;
+use target_device::{EVSYS, PM};
+
+/// The peripheral events that can be routed to a channel.
+/// See 24.8.2 CHANNEL in the datasheet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Generator {
+    RtcCmp0 = 0x01,
+    RtcCmp1 = 0x02,
+    RtcOvf = 0x03,
+    RtcPer0 = 0x04,
+    RtcPer1 = 0x05,
+    RtcPer2 = 0x06,
+    RtcPer3 = 0x07,
+    RtcPer4 = 0x08,
+    RtcPer5 = 0x09,
+    RtcPer6 = 0x0a,
+    RtcPer7 = 0x0b,
+    ExtInt0 = 0x0c,
+    ExtInt1 = 0x0d,
+    ExtInt2 = 0x0e,
+    ExtInt3 = 0x0f,
+    ExtInt4 = 0x10,
+    ExtInt5 = 0x11,
+    ExtInt6 = 0x12,
+    ExtInt7 = 0x13,
+    ExtInt8 = 0x14,
+    ExtInt9 = 0x15,
+    ExtInt10 = 0x16,
+    ExtInt11 = 0x17,
+    ExtInt12 = 0x18,
+    ExtInt13 = 0x19,
+    ExtInt14 = 0x1a,
+    ExtInt15 = 0x1b,
+    DmacCh0 = 0x1c,
+    DmacCh1 = 0x1d,
+    DmacCh2 = 0x1e,
+    DmacCh3 = 0x1f,
+    Tcc0Ovf = 0x20,
+    Tcc0Trg = 0x21,
+    Tcc0Cnt = 0x22,
+    Tcc0Mc0 = 0x23,
+    Tcc0Mc1 = 0x24,
+    Tcc0Mc2 = 0x25,
+    Tcc0Mc3 = 0x26,
+    Tcc1Ovf = 0x27,
+    Tcc1Trg = 0x28,
+    Tcc1Cnt = 0x29,
+    Tcc1Mc0 = 0x2a,
+    Tcc1Mc1 = 0x2b,
+    Tcc2Ovf = 0x2c,
+    Tcc2Trg = 0x2d,
+    Tcc2Cnt = 0x2e,
+    Tcc2Mc0 = 0x2f,
+    Tcc2Mc1 = 0x30,
+    Tc3Ovf = 0x31,
+    Tc3Mc0 = 0x32,
+    Tc3Mc1 = 0x33,
+    Tc4Ovf = 0x34,
+    Tc4Mc0 = 0x35,
+    Tc4Mc1 = 0x36,
+    Tc5Ovf = 0x37,
+    Tc5Mc0 = 0x38,
+    Tc5Mc1 = 0x39,
+    AdcResrdy = 0x40,
+    AdcWinmon = 0x41,
+    AcComp0 = 0x42,
+    AcComp1 = 0x43,
+    AcWin0 = 0x44,
+    DacEmpty = 0x45,
+}
+
+/// The software event bit in the CHANNEL register
+const SWEVT: u16 = 1 << 8;
+
+/// The number of users connected to each channel.  This is only
+/// modified inside a critical section.
+static mut CONNECTED_USERS: [u8; 12] = [0; 12];
+
+fn connected_users(channel: u8) -> u8 {
+    interrupt::free(|_| unsafe { CONNECTED_USERS[channel as usize] })
+}
+
+fn update_connected_users(channel: u8, connected: bool) {
+    interrupt::free(|_| unsafe {
+        let count = &mut CONNECTED_USERS[channel as usize];
+        if connected {
+            *count += 1;
+        } else {
+            *count -= 1;
+        }
+    });
+}
+
+/// Selects which edges of the generator signal produce an event on
+/// a synchronous or resynchronized channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Edge {
+    Rising,
+    Falling,
+    Both,
+}
+
+/// `EventSystem` owns the EVSYS peripheral.
+pub struct EventSystem {
+    evsys: EVSYS,
+}
+
+impl EventSystem {
+    /// Power up the event system and return it together with the
+    /// tokens for its channels and users.
+    pub fn new(evsys: EVSYS, pm: &mut PM) -> (Self, Channels, Users) {
+        // this is safe because we're constrained to just the evsys bit
+        pm.apbcmask.modify(|_, w| w.evsys_().set_bit());
+
+        evsys.ctrl.write(|w| w.swrst().set_bit());
+
+        (Self { evsys }, Channels::new(), Users::new())
+    }
+
+    /// Release the EVSYS peripheral.
+    /// All of the channel and user tokens must be returned so that
+    /// nothing remains routed.
+    pub fn free(self, _channels: Channels, _users: Users) -> EVSYS {
+        self.evsys.ctrl.write(|w| w.swrst().set_bit());
+        self.evsys
+    }
+}
+
+/// Identifies an event channel.
+/// It doesn't make sense to implement this trait outside of this module.
+pub trait ChannelId {
+    const ID: u8;
+    /// The generic clock used by the channel on the synchronous and
+    /// resynchronized paths
+    type Clock;
+}
+
+/// An unused event channel.  The `CH` type identifies the hardware
+/// channel.
+pub struct Channel<CH> {
+    _ch: PhantomData<CH>,
+}
+
+impl<CH> Channel<CH>
+where
+    CH: ChannelId,
+{
+    fn route(self, generator: Generator, path: u8, edge: Option<Edge>) -> EventChannel<CH> {
+        let evsys = unsafe { &*EVSYS::ptr() };
+        // The channel is selected by the write itself, so this does
+        // not disturb the configuration of the other channels
+        evsys.channel.write(|w| unsafe {
+            w.channel().bits(CH::ID);
+            w.evgen().bits(generator as u8);
+            w.path().bits(path);
+            match edge {
+                None => w.edgsel().no_evt_output(),
+                Some(Edge::Rising) => w.edgsel().rising_edge(),
+                Some(Edge::Falling) => w.edgsel().falling_edge(),
+                Some(Edge::Both) => w.edgsel().both_edges(),
+            }
+        });
+        EventChannel { _ch: PhantomData }
+    }
+
+    /// Route `generator` to the channel using the asynchronous path.
+    /// Events are passed straight through to the users without any
+    /// clock, so this path works in sleep modes where the clocks are
+    /// stopped, but the channel can't detect events or raise
+    /// interrupts.
+    pub fn asynchronous(self, generator: Generator) -> EventChannel<CH> {
+        self.route(generator, 2, None)
+    }
+
+    /// Route `generator` to the channel using the synchronous path.
+    /// The generator must be clocked by the same generic clock as the
+    /// channel.
+    pub fn synchronous(
+        self,
+        _clock: &CH::Clock,
+        generator: Generator,
+        edge: Edge,
+    ) -> EventChannel<CH> {
+        self.route(generator, 0, Some(edge))
+    }
+
+    /// Route `generator` to the channel using the resynchronized path.
+    /// This is used when the generator is clocked by a different
+    /// generic clock to the channel.
+    pub fn resynchronized(
+        self,
+        _clock: &CH::Clock,
+        generator: Generator,
+        edge: Edge,
+    ) -> EventChannel<CH> {
+        self.route(generator, 1, Some(edge))
+    }
+}
+
+/// An event channel that has been routed from a generator.
+pub struct EventChannel<CH> {
+    _ch: PhantomData<CH>,
+}
+
+impl<CH> EventChannel<CH>
+where
+    CH: ChannelId,
+{
+    fn evsys(&self) -> &RegisterBlock {
+        // This is safe because each channel only touches the bits
+        // that belong to it, and the registers that are shared
+        // between channels are only ever written.
+        unsafe { &*EVSYS::ptr() }
+    }
+
+    /// The bit offset of this channel in the CHSTATUS and INTFLAG
+    /// registers; channels 8 to 11 are in the upper half.
+    fn shift(&self) -> u32 {
+        if CH::ID < 8 {
+            CH::ID as u32
+        } else {
+            CH::ID as u32 + 8
+        }
+    }
+
+    /// Returns true while an event is being passed to the users
+    pub fn is_busy(&self) -> bool {
+        self.evsys().chstatus.read().bits() & (1 << (self.shift() + 8)) != 0
+    }
+
+    /// Returns true once all of the users connected to the channel are
+    /// ready to handle an event
+    pub fn users_ready(&self) -> bool {
+        self.evsys().chstatus.read().bits() & (1 << self.shift()) != 0
+    }
+
+    /// Issue an event on the channel from software
+    pub fn trigger(&mut self) {
+        // SWEVT must be written together with the channel number using
+        // a 16-bit write, so that the routing in the upper half of the
+        // register is left untouched
+        let channel = &self.evsys().channel as *const _ as *mut u16;
+        unsafe { ptr::write_volatile(channel, SWEVT | CH::ID as u16) };
+    }
+
+    /// Enable the event detected interrupt for this channel.  This is
+    /// only available on the synchronous and resynchronized paths.
+    /// This method only sets the EVSYS configuration to trigger
+    /// the interrupt; it does not configure the interrupt controller
+    /// or define an interrupt handler.
+    pub fn enable_interrupt(&mut self) {
+        let mask = 1 << (self.shift() + 8);
+        self.evsys().intenset.write(|w| unsafe { w.bits(mask) });
+    }
+
+    /// Disables the event detected interrupt for this channel.
+    pub fn disable_interrupt(&mut self) {
+        let mask = 1 << (self.shift() + 8);
+        self.evsys().intenclr.write(|w| unsafe { w.bits(mask) });
+    }
+
+    /// Returns true if an event has been detected on the channel,
+    /// clearing the flag.
+    pub fn event_detected(&mut self) -> bool {
+        let mask = 1 << (self.shift() + 8);
+        self.test_and_clear(mask)
+    }
+
+    /// Returns true if an event was raised while the users were still
+    /// busy with a previous event, clearing the flag.
+    pub fn overrun(&mut self) -> bool {
+        let mask = 1 << self.shift();
+        self.test_and_clear(mask)
+    }
+
+    fn test_and_clear(&mut self, mask: u32) -> bool {
+        if self.evsys().intflag.read().bits() & mask != 0 {
+            // Writing a 1 clears the flag
+            self.evsys().intflag.write(|w| unsafe { w.bits(mask) });
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Disconnect the generator from the channel and return the
+    /// channel token.  The routed channel is returned as an error if
+    /// any users are still connected to it; they must be disconnected
+    /// first so that they can't receive the events of another generator.
+    pub fn free(mut self) -> Result<Channel<CH>, Self> {
+        if connected_users(CH::ID) != 0 {
+            return Err(self);
+        }
+        self.disable_interrupt();
+        self.evsys()
+            .channel
+            .write(|w| unsafe { w.channel().bits(CH::ID) });
+        Ok(Channel { _ch: PhantomData })
+    }
+}
+
+/// Identifies an event user.
+/// It doesn't make sense to implement this trait outside of this module.
+pub trait UserId {
+    const ID: u8;
+}
+
+/// The token for an event user.  The `U` type identifies the
+/// peripheral input that receives the events.
+pub struct User<U> {
+    _user: PhantomData<U>,
+}
+
+impl<U> User<U>
+where
+    U: UserId,
+{
+    /// Connect the user to `channel`.  The peripheral must also be
+    /// configured to act on the incoming event.
+    pub fn connect<CH: ChannelId>(self, _channel: &EventChannel<CH>) -> ConnectedUser<U> {
+        let evsys = unsafe { &*EVSYS::ptr() };
+        evsys.user.write(|w| unsafe {
+            w.user().bits(U::ID);
+            // Zero means no channel, so the value is offset by one
+            w.channel().bits(CH::ID + 1)
+        });
+        update_connected_users(CH::ID, true);
+        ConnectedUser {
+            _user: PhantomData,
+            channel: CH::ID,
+        }
+    }
+}
+
+/// An event user connected to a channel.
+pub struct ConnectedUser<U> {
+    _user: PhantomData<U>,
+    channel: u8,
+}
+
+impl<U> ConnectedUser<U>
+where
+    U: UserId,
+{
+    /// Disconnect the user from its channel and return the user token.
+    pub fn disconnect(self) -> User<U> {
+        let evsys = unsafe { &*EVSYS::ptr() };
+        evsys.user.write(|w| unsafe {
+            w.user().bits(U::ID);
+            w.channel().bits(0)
+        });
+        update_connected_users(self.channel, false);
+        User { _user: PhantomData }
+    }
+}
+
+macro_rules! channels {
+    ($($CH:ident: ($ch:ident, $id:expr, $Clock:ident),)+) => {
+        $(
+/// Identifies the event channel with the matching number
+pub struct $CH;
+
+impl ChannelId for $CH {
+    const ID: u8 = $id;
+    type Clock = clock::$Clock;
+}
+        )+
+
+/// Holds the individual event channels
+pub struct Channels {
+    $(
+        pub $ch: Channel<$CH>,
+    )+
+}
+
+impl Channels {
+    fn new() -> Self {
+        Self {
+            $(
+                $ch: Channel { _ch: PhantomData },
+            )+
+        }
+    }
+}
+    }
+}
+
+channels! {
+    Ch0: (ch0, 0, Evsys0Clock),
+    Ch1: (ch1, 1, Evsys1Clock),
+    Ch2: (ch2, 2, Evsys2Clock),
+    Ch3: (ch3, 3, Evsys3Clock),
+    Ch4: (ch4, 4, Evsys4Clock),
+    Ch5: (ch5, 5, Evsys5Clock),
+    Ch6: (ch6, 6, Evsys6Clock),
+    Ch7: (ch7, 7, Evsys7Clock),
+    Ch8: (ch8, 8, Evsys8Clock),
+    Ch9: (ch9, 9, Evsys9Clock),
+    Ch10: (ch10, 10, Evsys10Clock),
+    Ch11: (ch11, 11, Evsys11Clock),
+}
+
+macro_rules! users {
+    ($($U:ident: ($user:ident, $id:expr),)+) => {
+        $(
+/// Identifies the event user with the matching name
+pub struct $U;
+
+impl UserId for $U {
+    const ID: u8 = $id;
+}
+        )+
+
+/// Holds the individual event users.
+/// See 24.8.3 USER in the datasheet.
+pub struct Users {
+    $(
+        pub $user: User<$U>,
+    )+
+}
+
+impl Users {
+    fn new() -> Self {
+        Self {
+            $(
+                $user: User { _user: PhantomData },
+            )+
+        }
+    }
+}
+    }
+}
+
+users! {
+    DmacCh0User: (dmac_ch0, 0x00),
+    DmacCh1User: (dmac_ch1, 0x01),
+    DmacCh2User: (dmac_ch2, 0x02),
+    DmacCh3User: (dmac_ch3, 0x03),
+    Tcc0Ev0User: (tcc0_ev0, 0x04),
+    Tcc0Ev1User: (tcc0_ev1, 0x05),
+    Tcc0Mc0User: (tcc0_mc0, 0x06),
+    Tcc0Mc1User: (tcc0_mc1, 0x07),
+    Tcc0Mc2User: (tcc0_mc2, 0x08),
+    Tcc0Mc3User: (tcc0_mc3, 0x09),
+    Tcc1Ev0User: (tcc1_ev0, 0x0a),
+    Tcc1Ev1User: (tcc1_ev1, 0x0b),
+    Tcc1Mc0User: (tcc1_mc0, 0x0c),
+    Tcc1Mc1User: (tcc1_mc1, 0x0d),
+    Tcc2Ev0User: (tcc2_ev0, 0x0e),
+    Tcc2Ev1User: (tcc2_ev1, 0x0f),
+    Tcc2Mc0User: (tcc2_mc0, 0x10),
+    Tcc2Mc1User: (tcc2_mc1, 0x11),
+    Tc3User: (tc3, 0x12),
+    Tc4User: (tc4, 0x13),
+    Tc5User: (tc5, 0x14),
+    AdcStartUser: (adc_start, 0x17),
+    AdcSyncUser: (adc_sync, 0x18),
+    AcComp0User: (ac_comp0, 0x19),
+    AcComp1User: (ac_comp1, 0x1a),
+    DacStartUser: (dac_start, 0x1b),
+}
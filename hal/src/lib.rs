@@ -48,14 +48,27 @@ pub extern crate usb_device;
 
 extern crate void;
 
+pub mod ac;
+#[cfg(feature = "unproven")]
+pub mod adc;
 mod calibration;
+#[macro_use]
 pub mod clock;
+pub mod dac;
 pub mod delay;
+pub mod dmac;
+pub mod eic;
+pub mod evsys;
 pub mod gpio;
 pub mod prelude;
+#[cfg(feature = "unproven")]
+pub mod pwm;
+pub mod rtc;
 pub mod sercom;
+pub mod sleep;
 pub mod time;
 pub mod timer;
+pub mod watchdog;
 
 #[cfg(feature = "usb")]
 pub mod usb;
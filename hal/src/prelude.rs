@@ -2,3 +2,4 @@
 pub use gpio::GpioExt as _atsamd21_hal_gpio_GpioExt;
 pub use hal::prelude::*;
 pub use time::U32Ext as _atsamd21_hal_time_U32Ext;
+pub use time::Monotonic as _atsamd21_hal_time_Monotonic;
@@ -0,0 +1,1031 @@
+//! Working with pulse width modulated outputs.
+//! The TCC peripherals generate PWM waveforms on up to four compare
+//! channels each.  Every channel drives one or more waveform outputs,
+//! which are routed to pins configured for peripheral function E or F.
+//! `Tcc0Pwm`, `Tcc1Pwm` and `Tcc2Pwm` implement the embedded_hal `Pwm`
+//! trait; the duty cycle of each channel is expressed as a count in the
+//! range 0 to `get_max_duty`, which depends on the period.
+//! The motor control features of the TCCs, such as dead-time insertion
+//! and fault handling, are configured via an `AdvancedConfig`.
+//! The simpler TC peripherals can also generate PWM waveforms on their
+//! two waveform outputs, leaving the TCCs free for other uses.
+//! `Tc3Pwm`, `Tc4Pwm` and `Tc5Pwm` return a handle for each channel that
+//! implements the embedded_hal `PwmPin` trait.
+use clock;
+use core::marker::PhantomData;
+use gpio::{
+    Pa0, Pa1, Pa10, Pa11, Pa12, Pa13, Pa14, Pa15, Pa16, Pa17, Pa18, Pa19, Pa20, Pa21, Pa22, Pa23,
+    Pa24, Pa25, Pa30, Pa31, Pa4, Pa5, Pa6, Pa7, Pa8, Pa9, PfE, PfF,
+};
+#[cfg(feature = "samd21g18a")]
+use gpio::{Pb10, Pb11, Pb8, Pb9};
+use hal::{Pwm, PwmPin};
+use target_device::tc3::COUNT16;
+use target_device::tcc0::RegisterBlock;
+use target_device::{PM, TC3, TC4, TC5, TCC0, TCC1, TCC2};
+use time::Hertz;
+use timer::{counter_params, TimerError};
+
+/// Compute the prescaler and TOP value that most closely generate
+/// `freq` from `src`, or describe why it can't be reached.
+/// TOP is kept below `max_top`, so that a compare value of TOP + 1,
+/// which holds the output high for the whole period, still fits in
+/// the compare register.
+fn period_params(
+    src: Hertz,
+    freq: Hertz,
+    max_top: u32,
+) -> Result<clock::CounterParams, TimerError> {
+    counter_params(src, freq, max_top - 1)
+}
+
+macro_rules! pwm {
+    ($($TYPE:ident: ($TCC:ident, $pm:ident, $clock:ident, $Channel:ident, $Pin:ident,
+        $max_top:expr, $outputs:expr,
+        [$($ch:ident: $cc:expr),+],
+        [$($(#[$attr:meta])* $PinType:ident: ($Pf:ident, $wo:expr),)+]),)+) => {
+        $(
+
+/// The compare channels of the matching TCC instance
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum $Channel {
+    $(
+        $ch = $cc,
+    )+
+}
+
+/// The pins that can be driven by the waveform outputs of the
+/// matching TCC instance.
+pub enum $Pin {
+    $(
+        $(#[$attr])*
+        $PinType($PinType<$Pf>),
+    )+
+}
+
+impl $Pin {
+    /// Returns the index of the waveform output that drives this pin
+    fn waveform_output(&self) -> usize {
+        match self {
+            $(
+                $(#[$attr])*
+                &$Pin::$PinType(_) => $wo,
+            )+
+        }
+    }
+}
+
+$(
+$(#[$attr])*
+impl From<$PinType<$Pf>> for $Pin {
+    fn from(pin: $PinType<$Pf>) -> Self {
+        $Pin::$PinType(pin)
+    }
+}
+)+
+
+/// PWM generation using the matching TCC instance.
+/// Channels start out enabled with a duty cycle of zero.
+pub struct $TYPE {
+    tcc: $TCC,
+    clock_freq: Hertz,
+    period: Hertz,
+    divider: u16,
+    top: u32,
+    dither: u32,
+    duty: [u32; 4],
+    enabled: [bool; 4],
+    outputs: [Option<$Pin>; $outputs],
+}
+
+impl $TYPE {
+    /// Configure the TCC to generate PWM waveforms with the specified
+    /// frequency.
+    /// The clock is obtained from the `GenericClockController` instance
+    /// and its frequency limits the resolution of the duty cycle.
+    /// Returns the TCC together with the error if the frequency can't
+    /// be generated from the clock.
+    pub fn new<F: Into<Hertz>>(
+        clock: &clock::$clock,
+        freq: F,
+        tcc: $TCC,
+        pm: &mut PM,
+    ) -> Result<Self, ($TCC, TimerError)> {
+        let params = match period_params(clock.freq(), freq.into(), $max_top) {
+            Ok(params) => params,
+            Err(err) => return Err((tcc, err)),
+        };
+
+        // this is safe because we're constrained to just the tcc bit
+        pm.apbcmask.modify(|_, w| w.$pm().set_bit());
+
+        tcc.ctrla.write(|w| w.swrst().set_bit());
+        while tcc.syncbusy.read().swrst().bit_is_set() {}
+
+        let mut pwm = Self {
+            tcc,
+            clock_freq: clock.freq(),
+            period: Hertz(0),
+            divider: 1,
+            top: 0,
+            dither: 0,
+            duty: [0; 4],
+            enabled: [true; 4],
+            outputs: Default::default(),
+        };
+        pwm.update_period(params, 0);
+        Ok(pwm)
+    }
+
+    /// Change the frequency of the waveforms, or return an error if it
+    /// can't be generated from the clock.  The duty cycle of each
+    /// channel is scaled so that it remains the same proportion of
+    /// the period.
+    pub fn try_set_period<P>(&mut self, period: P) -> Result<(), TimerError>
+    where
+        P: Into<Hertz>,
+    {
+        let dither = self.dither;
+        // TOP stays below the scaled down maximum, so that the full duty
+        // value of (TOP + 1) << dither still fits in the PER and CC
+        // registers
+        let params = period_params(self.clock_freq, period.into(), $max_top >> dither)?;
+        self.update_period(params, dither);
+        Ok(())
+    }
+
+    /// Disable the TCC and release it, together with the pins that
+    /// were routed to its waveform outputs.
+    pub fn free(self) -> ($TCC, [Option<$Pin>; $outputs]) {
+        self.tcc.ctrla.modify(|_, w| w.enable().clear_bit());
+        while self.tcc.syncbusy.read().enable().bit_is_set() {}
+        (self.tcc, self.outputs)
+    }
+
+    /// Take ownership of `pin`, which is driven by one of the waveform
+    /// outputs.  Returns the pin that was previously routed to the
+    /// same waveform output, if any.
+    pub fn route_output(&mut self, pin: $Pin) -> Option<$Pin> {
+        let wo = pin.waveform_output();
+        self.outputs[wo].replace(pin)
+    }
+
+    /// Stop tracking the pin that is driven by waveform output `wo`
+    /// and return it.
+    pub fn release_output(&mut self, wo: usize) -> Option<$Pin> {
+        self.outputs.get_mut(wo).and_then(|output| output.take())
+    }
+
+    /// Configure whether the TCC keeps generating its waveforms in
+    /// standby sleep mode.  The TCC clock generator must also run in
+    /// standby.
+    pub fn run_in_standby(&mut self, enabled: bool) {
+        let was_enabled = self.tcc.ctrla.read().enable().bit_is_set();
+        self.tcc.ctrla.modify(|_, w| w.enable().clear_bit());
+        self.wait_for_sync();
+        self.tcc.ctrla.modify(|_, w| {
+            w.runstdby().bit(enabled);
+            w.enable().bit(was_enabled)
+        });
+        self.wait_for_sync();
+    }
+
+    fn wait_for_sync(&self) {
+        while self.tcc.syncbusy.read().bits() != 0 {}
+    }
+
+    fn write_cc(&mut self, channel: usize, value: u32) {
+        self.tcc.cc()[channel].write(|w| unsafe { w.cc().bits(value) });
+        self.wait_for_sync();
+    }
+
+    /// Reprogram the prescaler, period and compare values
+    fn configure(&mut self) {
+        self.tcc.ctrla.modify(|_, w| w.enable().clear_bit());
+        self.wait_for_sync();
+
+        let divider = self.divider;
+        self.tcc.ctrla.modify(|_, w| set_prescaler!(w, divider));
+        // Single slope PWM; the outputs are set at the start of the
+        // period and cleared when the count matches the channel
+        self.tcc.wave.write(|w| w.wavegen().npwm());
+        // The dithering cycle count occupies the low bits of PER
+        let per = self.top << self.dither;
+        self.tcc.per().write(|w| unsafe { w.per().bits(per) });
+        self.wait_for_sync();
+        for &channel in [$($cc),+].iter() {
+            let value = if self.enabled[channel] {
+                self.duty[channel]
+            } else {
+                0
+            };
+            self.write_cc(channel, value);
+        }
+
+        self.tcc.ctrla.modify(|_, w| w.enable().set_bit());
+        self.wait_for_sync();
+    }
+
+    /// Apply the prescaler and TOP value from `params`, scale the duty
+    /// cycles to match and reprogram the TCC
+    fn update_period(&mut self, params: clock::CounterParams, dither: u32) {
+        let old_max = self.get_max_duty() as u64;
+        self.divider = params.divider;
+        self.top = params.top;
+        self.dither = dither;
+        self.period = params.effective_freq;
+        let new_max = self.get_max_duty() as u64;
+        for duty in self.duty.iter_mut() {
+            *duty = (*duty as u64 * new_max / old_max) as u32;
+        }
+        self.configure();
+    }
+
+    /// Apply the motor control features described by `config`.  The TCC
+    /// is briefly stopped while they are reconfigured.
+    /// When dithering is enabled the duty cycle gains extra fractional
+    /// bits; `get_max_duty` reflects the increased resolution and the
+    /// existing duty cycles are scaled to match.
+    /// Dithering narrows the range of TOP values, so an error is
+    /// returned, without changing the TCC, if the current period can't
+    /// be kept.
+    pub fn configure_advanced(&mut self, config: &AdvancedConfig<$TCC>) -> Result<(), TimerError> {
+        let dither = config.dithering.bits();
+        let params = period_params(self.clock_freq, self.period, $max_top >> dither)?;
+        self.tcc.ctrla.modify(|_, w| w.enable().clear_bit());
+        self.wait_for_sync();
+        config.apply(&self.tcc);
+        self.update_period(params, dither);
+        Ok(())
+    }
+
+    /// Returns true while any of the fault inputs is active, or a
+    /// fault that must be cleared by software is being held.
+    pub fn is_faulted(&self) -> bool {
+        let status = self.tcc.status.read();
+        status.faulta().bit_is_set()
+            || status.faultb().bit_is_set()
+            || status.fault0().bit_is_set()
+            || status.fault1().bit_is_set()
+    }
+
+    /// Clear the fault state, resuming the waveforms once the fault
+    /// inputs have become inactive.
+    pub fn clear_faults(&mut self) {
+        // Writing a 1 clears the state
+        self.tcc.status.write(|w| {
+            w.faulta().set_bit();
+            w.faultb().set_bit();
+            w.fault0().set_bit();
+            w.fault1().set_bit()
+        });
+        self.tcc.intflag.write(|w| {
+            w.faulta().set_bit();
+            w.faultb().set_bit();
+            w.fault0().set_bit();
+            w.fault1().set_bit()
+        });
+    }
+}
+
+impl Pwm for $TYPE {
+    type Channel = $Channel;
+    type Time = Hertz;
+    type Duty = u32;
+
+    fn disable(&mut self, channel: Self::Channel) {
+        let channel = channel as usize;
+        self.enabled[channel] = false;
+        self.write_cc(channel, 0);
+    }
+
+    fn enable(&mut self, channel: Self::Channel) {
+        let channel = channel as usize;
+        self.enabled[channel] = true;
+        let duty = self.duty[channel];
+        self.write_cc(channel, duty);
+    }
+
+    fn get_period(&self) -> Self::Time {
+        self.period
+    }
+
+    fn get_duty(&self, channel: Self::Channel) -> Self::Duty {
+        self.duty[channel as usize]
+    }
+
+    /// Returns the duty value that keeps the output high for the
+    /// whole period.
+    fn get_max_duty(&self) -> Self::Duty {
+        (self.top + 1) << self.dither
+    }
+
+    fn set_duty(&mut self, channel: Self::Channel, duty: Self::Duty) {
+        let duty = duty.min(self.get_max_duty());
+        let channel = channel as usize;
+        self.duty[channel] = duty;
+        if self.enabled[channel] {
+            self.write_cc(channel, duty);
+        }
+    }
+
+    /// Change the frequency of the waveforms.  The duty cycle of each
+    /// channel is scaled so that it remains the same proportion of
+    /// the period.  This panics if the frequency can't be generated;
+    /// `try_set_period` reports the error instead.
+    fn set_period<P>(&mut self, period: P)
+    where
+        P: Into<Self::Time>,
+    {
+        if let Err(err) = self.try_set_period(period) {
+            panic!("PWM period can't be set: {:?}", err);
+        }
+    }
+}
+        )+
+    };
+}
+
+pwm! {
+    Tcc0Pwm: (TCC0, tcc0_, Tcc0Tcc1Clock, Tcc0Channel, Tcc0Pin, 0xff_ffff, 8,
+        [_0: 0, _1: 1, _2: 2, _3: 3],
+        [
+            Pa4: (PfE, 0),
+            Pa5: (PfE, 1),
+            Pa8: (PfE, 0),
+            Pa9: (PfE, 1),
+            Pa10: (PfF, 2),
+            Pa11: (PfF, 3),
+            Pa12: (PfF, 6),
+            Pa13: (PfF, 7),
+            Pa14: (PfF, 4),
+            Pa15: (PfF, 5),
+            Pa16: (PfF, 6),
+            Pa17: (PfF, 7),
+            Pa18: (PfF, 2),
+            Pa19: (PfF, 3),
+            Pa20: (PfF, 6),
+            Pa21: (PfF, 7),
+            Pa22: (PfF, 4),
+            Pa23: (PfF, 5),
+            #[cfg(feature = "samd21g18a")]
+            Pb10: (PfF, 4),
+            #[cfg(feature = "samd21g18a")]
+            Pb11: (PfF, 5),
+        ]),
+    Tcc1Pwm: (TCC1, tcc1_, Tcc0Tcc1Clock, Tcc1Channel, Tcc1Pin, 0xff_ffff, 4,
+        [_0: 0, _1: 1],
+        [
+            Pa6: (PfE, 0),
+            Pa7: (PfE, 1),
+            Pa8: (PfF, 2),
+            Pa9: (PfF, 3),
+            Pa10: (PfE, 0),
+            Pa11: (PfE, 1),
+            Pa24: (PfF, 2),
+            Pa25: (PfF, 3),
+            Pa30: (PfE, 0),
+            Pa31: (PfE, 1),
+        ]),
+    Tcc2Pwm: (TCC2, tcc2_, Tcc2Tc3Clock, Tcc2Channel, Tcc2Pin, 0xffff, 2,
+        [_0: 0, _1: 1],
+        [
+            Pa0: (PfE, 0),
+            Pa1: (PfE, 1),
+            Pa12: (PfE, 0),
+            Pa13: (PfE, 1),
+            Pa16: (PfE, 0),
+            Pa17: (PfE, 1),
+        ]),
+}
+
+macro_rules! pattern {
+    ($($TYPE:ident,)+) => {
+        $(
+impl $TYPE {
+    /// Override the waveform outputs with a static pattern, as used
+    /// for commutating a brushless motor.  Bit n of `enable` replaces
+    /// waveform output n with bit n of `value`.  The new pattern takes
+    /// effect at the start of the next period.
+    pub fn set_pattern(&mut self, enable: u8, value: u8) {
+        self.tcc
+            .pattb
+            .write(|w| unsafe { w.bits(enable as u16 | (value as u16) << 8) });
+        self.wait_for_sync();
+    }
+}
+        )+
+    };
+}
+
+pattern! {
+    Tcc0Pwm,
+    Tcc1Pwm,
+}
+
+/// Dithering extends the resolution of the duty cycle by varying the
+/// compare value over a sequence of 16, 32 or 64 periods.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dithering {
+    None,
+    Dith4,
+    Dith5,
+    Dith6,
+}
+
+impl Dithering {
+    /// Returns the number of fractional bits added to the duty cycle
+    fn bits(self) -> u32 {
+        match self {
+            Dithering::None => 0,
+            Dithering::Dith4 => 4,
+            Dithering::Dith5 => 5,
+            Dithering::Dith6 => 6,
+        }
+    }
+}
+
+/// Selects which compare channel drives each of the waveform outputs
+/// of TCC0.
+/// See 31.6.3.8 Waveform Extension in the datasheet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputMatrix {
+    /// Waveform output n is driven by channel n modulo 4
+    Default,
+    /// Waveform output n is driven by channel n modulo 2
+    Pairs,
+    /// All of the waveform outputs are driven by channel 0
+    Channel0,
+    /// Waveform output 0 is driven by channel 0 and the others by
+    /// channel 1
+    Channel0And1,
+}
+
+/// The condition that starts fault blanking, during which the fault
+/// input is ignored
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlankingStart {
+    Rise,
+    Fall,
+    Both,
+}
+
+/// How the outputs are halted while a recoverable fault is active
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FaultHalt {
+    /// The outputs resume as soon as the fault input becomes inactive
+    Hardware,
+    /// The outputs remain halted until `clear_faults` is called
+    Software,
+}
+
+/// Configuration for a recoverable fault input.  Recoverable fault A
+/// is driven by the channel 0 event input of the TCC and fault B by
+/// the channel 1 event input; the event system routes these from the
+/// EIC or the analog comparator.
+#[derive(Clone, Copy, Debug)]
+pub struct RecoverableFault {
+    /// Treat the fault input as active low
+    pub invert: bool,
+    pub halt: FaultHalt,
+    /// Restart the counter when the fault becomes inactive
+    pub restart: bool,
+    /// Keep the outputs halted until the end of the period in which
+    /// the fault became inactive
+    pub keep: bool,
+    /// Only act on the fault while the compare channel is high
+    pub qualify: bool,
+    /// Ignore the fault for a number of prescaled clock cycles after
+    /// the selected edge of the waveform
+    pub blanking: Option<(BlankingStart, u8)>,
+    /// The number of consecutive samples required to register a
+    /// fault; zero disables the filter.  Must be less than 16.
+    pub filter: u8,
+}
+
+impl Default for RecoverableFault {
+    fn default() -> Self {
+        Self {
+            invert: false,
+            halt: FaultHalt::Hardware,
+            restart: false,
+            keep: false,
+            qualify: false,
+            blanking: None,
+            filter: 0,
+        }
+    }
+}
+
+/// The event inputs that can act as non-recoverable fault inputs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FaultInput {
+    Event0,
+    Event1,
+}
+
+macro_rules! write_fault {
+    ($register:expr, $fault:expr) => {
+        match $fault {
+            None => $register.reset(),
+            Some(fault) => $register.write(|w| unsafe {
+                if fault.invert {
+                    w.src().invert();
+                } else {
+                    w.src().enable();
+                }
+                match fault.halt {
+                    FaultHalt::Hardware => w.halt().hw(),
+                    FaultHalt::Software => w.halt().sw(),
+                };
+                match fault.blanking {
+                    None => w.blank().none(),
+                    Some((BlankingStart::Rise, cycles)) => w.blank().rise().blankval().bits(cycles),
+                    Some((BlankingStart::Fall, cycles)) => w.blank().fall().blankval().bits(cycles),
+                    Some((BlankingStart::Both, cycles)) => w.blank().both().blankval().bits(cycles),
+                };
+                w.restart().bit(fault.restart);
+                w.keep().bit(fault.keep);
+                w.qual().bit(fault.qualify);
+                w.filterval().bits(fault.filter & 0xf)
+            }),
+        }
+    };
+}
+
+/// A builder describing the motor control features of a TCC, which
+/// is applied via the `configure_advanced` method of the PWM driver.
+/// Features that are not present on a given TCC instance are not
+/// available on its builder.
+pub struct AdvancedConfig<TCC> {
+    dithering: Dithering,
+    output_matrix: OutputMatrix,
+    dead_time: Option<(u8, u8, u8)>,
+    fault_a: Option<RecoverableFault>,
+    fault_b: Option<RecoverableFault>,
+    non_recoverable: [Option<u8>; 2],
+    fault_outputs: (u8, u8),
+    _tcc: PhantomData<TCC>,
+}
+
+impl<TCC> AdvancedConfig<TCC> {
+    /// Create a configuration with all of the features disabled
+    pub fn new() -> Self {
+        Self {
+            dithering: Dithering::None,
+            output_matrix: OutputMatrix::Default,
+            dead_time: None,
+            fault_a: None,
+            fault_b: None,
+            non_recoverable: [None, None],
+            fault_outputs: (0, 0),
+            _tcc: PhantomData,
+        }
+    }
+
+    /// Configure recoverable fault A
+    pub fn recoverable_fault_a(mut self, fault: RecoverableFault) -> Self {
+        self.fault_a = Some(fault);
+        self
+    }
+
+    /// Configure recoverable fault B
+    pub fn recoverable_fault_b(mut self, fault: RecoverableFault) -> Self {
+        self.fault_b = Some(fault);
+        self
+    }
+
+    /// Use `input` as a non-recoverable fault input.  `filter` is the
+    /// number of consecutive samples required to register a fault;
+    /// zero disables the filter.  Must be less than 16.
+    /// The outputs are overridden as described by `fault_outputs`
+    /// until `clear_faults` is called.
+    pub fn non_recoverable_fault(mut self, input: FaultInput, filter: u8) -> Self {
+        self.non_recoverable[input as usize] = Some(filter & 0xf);
+        self
+    }
+
+    /// Select the state of the waveform outputs while a non-recoverable
+    /// fault is active.  Bit n of `enable` forces waveform output n to
+    /// bit n of `value`; the other outputs are unaffected.
+    pub fn fault_outputs(mut self, enable: u8, value: u8) -> Self {
+        self.fault_outputs = (enable, value);
+        self
+    }
+
+    fn apply(&self, tcc: &RegisterBlock) {
+        tcc.ctrla.modify(|_, w| match self.dithering {
+            Dithering::None => w.resolution().none(),
+            Dithering::Dith4 => w.resolution().dith4(),
+            Dithering::Dith5 => w.resolution().dith5(),
+            Dithering::Dith6 => w.resolution().dith6(),
+        });
+
+        tcc.wexctrl.write(|w| unsafe {
+            w.otmx().bits(match self.output_matrix {
+                OutputMatrix::Default => 0,
+                OutputMatrix::Pairs => 1,
+                OutputMatrix::Channel0 => 2,
+                OutputMatrix::Channel0And1 => 3,
+            });
+            if let Some((channels, low, high)) = self.dead_time {
+                w.dtien0().bit(channels & 1 != 0);
+                w.dtien1().bit(channels & 2 != 0);
+                w.dtien2().bit(channels & 4 != 0);
+                w.dtien3().bit(channels & 8 != 0);
+                w.dtls().bits(low);
+                w.dths().bits(high);
+            }
+            w
+        });
+
+        write_fault!(tcc.fctrla, self.fault_a);
+        write_fault!(tcc.fctrlb, self.fault_b);
+
+        let (enable, value) = self.fault_outputs;
+        let filter0 = self.non_recoverable[0].unwrap_or(0) as u32;
+        let filter1 = self.non_recoverable[1].unwrap_or(0) as u32;
+        tcc.drvctrl.write(|w| unsafe {
+            w.bits(enable as u32 | (value as u32) << 8 | filter0 << 24 | filter1 << 28)
+        });
+
+        tcc.evctrl.modify(|_, w| {
+            w.mcei0().bit(self.fault_a.is_some());
+            w.mcei1().bit(self.fault_b.is_some());
+            w.tcei0().bit(self.non_recoverable[0].is_some());
+            w.tcei1().bit(self.non_recoverable[1].is_some());
+            if self.non_recoverable[0].is_some() {
+                w.evact0().fault();
+            } else {
+                w.evact0().off();
+            }
+            if self.non_recoverable[1].is_some() {
+                w.evact1().fault()
+            } else {
+                w.evact1().off()
+            }
+        });
+    }
+}
+
+macro_rules! dithering {
+    ($($TCC:ident,)+) => {
+        $(
+impl AdvancedConfig<$TCC> {
+    /// Enable dithering of the duty cycle
+    pub fn dithering(mut self, dithering: Dithering) -> Self {
+        self.dithering = dithering;
+        self
+    }
+}
+        )+
+    };
+}
+
+dithering! {
+    TCC0,
+    TCC1,
+}
+
+impl AdvancedConfig<TCC0> {
+    /// Select which compare channel drives each waveform output
+    pub fn output_matrix(mut self, output_matrix: OutputMatrix) -> Self {
+        self.output_matrix = output_matrix;
+        self
+    }
+
+    /// Drive each of `channels` as a complementary pair, with the low
+    /// side on waveform output n and the high side on waveform output
+    /// n + 4.  `low` and `high` are the dead times, in prescaled clock
+    /// cycles, inserted before the low and high side outputs are
+    /// switched on.
+    pub fn dead_time(mut self, channels: &[Tcc0Channel], low: u8, high: u8) -> Self {
+        let mask = channels
+            .iter()
+            .fold(0, |mask, &channel| mask | 1 << channel as u8);
+        self.dead_time = Some((mask, low, high));
+        self
+    }
+}
+
+/// The mode of a TC PWM driver created by `new`, generating normal PWM
+/// waveforms on both channels
+pub struct TwoChannel;
+
+/// The mode of a TC PWM driver created by `new_exact`, in which
+/// channel 0 holds the period and only channel 1 drives an output
+pub struct ExactFrequency;
+
+macro_rules! tc_pwm {
+    ($($TYPE:ident: ($TC:ident, $pm:ident, $clock:ident, $Ch0:ident, $Ch1:ident,
+        $Wo0:ident: [$($(#[$attr0:meta])* $Pin0:ident),+],
+        $Wo1:ident: [$($(#[$attr1:meta])* $Pin1:ident),+]),)+) => {
+        $(
+
+/// The pins that can be driven by waveform output 0 of the matching
+/// TC instance.  These must be configured for peripheral function E.
+pub enum $Wo0 {
+    $(
+        $(#[$attr0])*
+        $Pin0($Pin0<PfE>),
+    )+
+}
+
+$(
+$(#[$attr0])*
+impl From<$Pin0<PfE>> for $Wo0 {
+    fn from(pin: $Pin0<PfE>) -> Self {
+        $Wo0::$Pin0(pin)
+    }
+}
+)+
+
+/// The pins that can be driven by waveform output 1 of the matching
+/// TC instance.  These must be configured for peripheral function E.
+pub enum $Wo1 {
+    $(
+        $(#[$attr1])*
+        $Pin1($Pin1<PfE>),
+    )+
+}
+
+$(
+$(#[$attr1])*
+impl From<$Pin1<PfE>> for $Wo1 {
+    fn from(pin: $Pin1<PfE>) -> Self {
+        $Wo1::$Pin1(pin)
+    }
+}
+)+
+
+/// PWM generation using the matching TC instance.  The `MODE` records
+/// which channel handles were handed out, so that all of them must be
+/// returned to `free`.
+pub struct $TYPE<MODE> {
+    tc: $TC,
+    period: Hertz,
+    _mode: PhantomData<MODE>,
+}
+
+impl<MODE> $TYPE<MODE> {
+    fn reset(tc: &$TC, pm: &mut PM) {
+        // this is safe because we're constrained to just the tc bit
+        pm.apbcmask.modify(|_, w| w.$pm().set_bit());
+
+        let count = tc.count16();
+        count.ctrla.write(|w| w.swrst().set_bit());
+        while count.status.read().syncbusy().bit_is_set() {}
+        // the SVD erroneously marks swrst as write-only, so we
+        // need to manually read the bit here
+        while count.ctrla.read().bits() & 1 != 0 {}
+    }
+
+    fn enable(&self) {
+        let count = self.tc.count16();
+        count.ctrla.modify(|_, w| w.enable().set_bit());
+        while count.status.read().syncbusy().bit_is_set() {}
+    }
+
+    fn disable(self) -> $TC {
+        let count = self.tc.count16();
+        count.ctrla.modify(|_, w| w.enable().clear_bit());
+        while count.status.read().syncbusy().bit_is_set() {}
+        self.tc
+    }
+
+    /// Returns the frequency of the generated waveforms.
+    pub fn period(&self) -> Hertz {
+        self.period
+    }
+
+    /// Configure whether the TC keeps generating its waveforms in
+    /// standby sleep mode.  The TC clock generator must also run in
+    /// standby.
+    pub fn run_in_standby(&mut self, enabled: bool) {
+        let count = self.tc.count16();
+        let was_enabled = count.ctrla.read().enable().bit_is_set();
+        count.ctrla.modify(|_, w| w.enable().clear_bit());
+        while count.status.read().syncbusy().bit_is_set() {}
+        count.ctrla.modify(|_, w| {
+            w.runstdby().bit(enabled);
+            w.enable().bit(was_enabled)
+        });
+        while count.status.read().syncbusy().bit_is_set() {}
+    }
+}
+
+impl $TYPE<TwoChannel> {
+    /// Configure the TC to generate normal PWM waveforms on both of its
+    /// channels, with a duty cycle of zero.
+    /// The period is 65536 counts so that the duty cycle has the full
+    /// 16 bit resolution, which means that the frequency can only be
+    /// adjusted in steps of the prescaler; the closest match to `freq`
+    /// is used.  Returns the TC together with the error if `freq` is
+    /// outside the range of the prescalers.
+    pub fn new<F: Into<Hertz>>(
+        clock: &clock::$clock,
+        freq: F,
+        tc: $TC,
+        pm: &mut PM,
+    ) -> Result<(Self, $Ch0, $Ch1), ($TC, TimerError)> {
+        let src = clock.freq();
+        let freq = freq.into();
+        // The prescaled clock has to run 65536 times faster than the
+        // waveform
+        let tick = freq.0 as u64 * 0x1_0000;
+        let params = if tick <= src.0 as u64 {
+            clock::CounterParams::prescaled(src, Hertz(tick as u32))
+        } else {
+            None
+        };
+        let params = match params {
+            Some(params) => params,
+            None if freq.0 > src.0 / 0x1_0000 => {
+                let max = Hertz(src.0 / 0x1_0000);
+                return Err((tc, TimerError::FrequencyTooHigh { requested: freq, max }));
+            }
+            None => {
+                let min = Hertz(((src.0 as u64 + 1024 * 0x1_0000 - 1) / (1024 * 0x1_0000)) as u32);
+                return Err((tc, TimerError::FrequencyTooLow { requested: freq, min }));
+            }
+        };
+
+        Self::reset(&tc, pm);
+
+        let count = tc.count16();
+        count.ctrla.modify(|_, w| {
+            set_prescaler!(w, params.divider);
+            w.wavegen().npwm()
+        });
+
+        let pwm = Self {
+            tc,
+            period: Hertz(params.effective_freq.0 / 0x1_0000),
+            _mode: PhantomData,
+        };
+        pwm.enable();
+        Ok((pwm, $Ch0::new(), $Ch1::new()))
+    }
+
+    /// Disable the TC and release it.
+    /// The channel handles must be returned so that their pins have
+    /// been released.
+    pub fn free(self, _ch0: $Ch0, _ch1: $Ch1) -> $TC {
+        self.disable()
+    }
+}
+
+impl $TYPE<ExactFrequency> {
+    /// Configure the TC to generate a PWM waveform with the frequency
+    /// closest to `freq`, with a duty cycle of zero.
+    /// Channel 0 holds the period in this mode, so only channel 1
+    /// is available for PWM output.  Returns the TC together with the
+    /// error if `freq` can't be generated from the clock.
+    pub fn new_exact<F: Into<Hertz>>(
+        clock: &clock::$clock,
+        freq: F,
+        tc: $TC,
+        pm: &mut PM,
+    ) -> Result<(Self, $Ch1), ($TC, TimerError)> {
+        let params = match period_params(clock.freq(), freq.into(), 0xffff) {
+            Ok(params) => params,
+            Err(err) => return Err((tc, err)),
+        };
+
+        Self::reset(&tc, pm);
+
+        let count = tc.count16();
+        count.ctrla.modify(|_, w| {
+            set_prescaler!(w, params.divider);
+            w.wavegen().mpwm()
+        });
+        count.cc[0].write(|w| unsafe { w.cc().bits(params.top as u16) });
+        while count.status.read().syncbusy().bit_is_set() {}
+
+        let pwm = Self {
+            tc,
+            period: params.effective_freq,
+            _mode: PhantomData,
+        };
+        pwm.enable();
+        Ok((pwm, $Ch1::new()))
+    }
+
+    /// Disable the TC and release it.
+    /// The channel handle must be returned so that its pin has been
+    /// released.
+    pub fn free(self, _ch1: $Ch1) -> $TC {
+        self.disable()
+    }
+}
+
+tc_pwm_channel!($TC, $Ch0, $Wo0, 0, inven0);
+tc_pwm_channel!($TC, $Ch1, $Wo1, 1, inven1);
+        )+
+    };
+}
+
+macro_rules! tc_pwm_channel {
+    ($TC:ident, $Type:ident, $Output:ident, $idx:expr, $inven:ident) => {
+        /// A handle to the PWM channel with the matching number.
+        /// The channel starts out enabled with a duty cycle of zero.
+        pub struct $Type {
+            output: Option<$Output>,
+            duty: u32,
+            enabled: bool,
+        }
+
+        impl $Type {
+            fn count(&self) -> &COUNT16 {
+                // This is safe because each handle only touches the registers
+                // that belong to its own channel.
+                unsafe { (*$TC::ptr()).count16() }
+            }
+
+            fn new() -> Self {
+                Self {
+                    output: None,
+                    duty: 0,
+                    enabled: true,
+                }
+            }
+
+            /// Program the compare value for `duty`.  A compare value of
+            /// TOP still clears the output at the end of the period, so
+            /// full duty is produced by inverting a duty of zero when
+            /// TOP + 1 doesn't fit in the compare register.
+            fn write_duty(&mut self, duty: u32) {
+                let count = self.count();
+                let full = duty > 0xffff;
+                count.ctrlc.modify(|_, w| w.$inven().bit(full));
+                while count.status.read().syncbusy().bit_is_set() {}
+                let value = if full { 0 } else { duty as u16 };
+                count.cc[$idx].write(|w| unsafe { w.cc().bits(value) });
+                while count.status.read().syncbusy().bit_is_set() {}
+            }
+
+            /// Take ownership of the pin driven by this channel.
+            /// Returns the pin that was previously routed, if any.
+            pub fn route_output(&mut self, pin: $Output) -> Option<$Output> {
+                self.output.replace(pin)
+            }
+
+            /// Stop tracking the output pin and return it.
+            pub fn release_output(&mut self) -> Option<$Output> {
+                self.output.take()
+            }
+        }
+
+        impl PwmPin for $Type {
+            type Duty = u32;
+
+            /// Stop driving the waveform; the output is held low.
+            fn disable(&mut self) {
+                self.enabled = false;
+                self.write_duty(0);
+            }
+
+            fn enable(&mut self) {
+                self.enabled = true;
+                let duty = self.duty;
+                self.write_duty(duty);
+            }
+
+            fn get_duty(&self) -> Self::Duty {
+                self.duty
+            }
+
+            /// Returns the duty value that keeps the output high for the
+            /// whole period.
+            fn get_max_duty(&self) -> Self::Duty {
+                let count = self.count();
+                let top = if count.ctrla.read().wavegen().is_mpwm() {
+                    count.cc[0].read().cc().bits()
+                } else {
+                    0xffff
+                };
+                top as u32 + 1
+            }
+
+            fn set_duty(&mut self, duty: Self::Duty) {
+                self.duty = duty.min(self.get_max_duty());
+                if self.enabled {
+                    let duty = self.duty;
+                    self.write_duty(duty);
+                }
+            }
+        }
+    };
+}
+
+tc_pwm! {
+    Tc3Pwm: (TC3, tc3_, Tcc2Tc3Clock, Tc3Pwm0, Tc3Pwm1,
+        Tc3Wo0: [Pa14, Pa18],
+        Tc3Wo1: [Pa15, Pa19]),
+    Tc4Pwm: (TC4, tc4_, Tc4Tc5Clock, Tc4Pwm0, Tc4Pwm1,
+        Tc4Wo0: [Pa22, #[cfg(feature = "samd21g18a")] Pb8],
+        Tc4Wo1: [Pa23, #[cfg(feature = "samd21g18a")] Pb9]),
+    Tc5Pwm: (TC5, tc5_, Tc4Tc5Clock, Tc5Pwm0, Tc5Pwm1,
+        Tc5Wo0: [Pa24, #[cfg(feature = "samd21g18a")] Pb10],
+        Tc5Wo1: [Pa25, #[cfg(feature = "samd21g18a")] Pb11]),
+}
@@ -0,0 +1,568 @@
+//! Working with the Real Time Counter.
+//! The RTC keeps running in standby sleep mode as long as its generic
+//! clock does, so its interrupts can be used to wake the device.
+//! It is normally clocked from a 32kHz oscillator and can operate in
+//! one of three modes:
+//!
+//! * `Count32Mode` is a 32-bit counter with a single compare value.
+//!   In this mode the RTC implements the `CountDown` timer trait,
+//!   which is handy for delays that span standby sleep, and can be
+//!   converted into a `Monotonic` clock.
+//! * `Count16Mode` is a 16-bit counter that wraps at a configurable
+//!   period, with two compare values.
+//! * `ClockMode` is a calendar counting seconds to years, with an
+//!   alarm.  It requires a 1024Hz clock, such as the 32kHz
+//!   oscillator divided by 32.
+use clock;
+use core::marker::PhantomData;
+use cortex_m::interrupt;
+use hal::timer::{CountDown, Periodic};
+use nb;
+use target_device::rtc::mode2::mask::SELW;
+use target_device::{PM, RTC};
+use time::{Fraction, Hertz, Instant, Monotonic, StaticMonotonic};
+use void::Void;
+
+/// Marker type for the 32-bit counter mode
+pub struct Count32Mode;
+/// Marker type for the 16-bit counter mode
+pub struct Count16Mode;
+/// Marker type for the clock/calendar mode
+pub struct ClockMode;
+
+/// The fields of the alarm that must match the current time for the
+/// alarm to trigger.  `MMSS`, for example, triggers once an hour.
+pub type AlarmMask = SELW;
+
+/// A date and time as kept by the RTC in `ClockMode`.  The RTC
+/// counts years from 2000 to 2063 and uses a 24 hour clock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+}
+
+/// The year represented by a YEAR field value of zero
+const BASE_YEAR: u16 = 2000;
+/// The largest value of the 6-bit YEAR field
+const MAX_YEAR_OFFSET: u16 = 63;
+
+/// The error returned when a `DateTime` can't be represented by the
+/// RTC.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RtcError {
+    /// The year is outside of the range 2000 to 2063.
+    YearOutOfRange { year: u16 },
+}
+
+/// Returns the value of the YEAR field for `year`.
+fn year_offset(year: u16) -> Result<u8, RtcError> {
+    match year.checked_sub(BASE_YEAR) {
+        Some(offset) if offset <= MAX_YEAR_OFFSET => Ok(offset as u8),
+        _ => Err(RtcError::YearOutOfRange { year }),
+    }
+}
+
+/// Selects one of the two compare values of `Count16Mode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compare {
+    Compare0,
+    Compare1,
+}
+
+/// `Rtc` represents the RTC peripheral configured in the mode
+/// identified by `MODE`.
+pub struct Rtc<MODE> {
+    rtc: RTC,
+    freq: Hertz,
+    _mode: PhantomData<MODE>,
+}
+
+/// Find the prescaler that divides `src` closest to `rate`, returned
+/// as the base 2 logarithm of the division.
+fn prescaler_shift(src: Hertz, rate: Hertz) -> u8 {
+    let error = |shift: u8| ((src.0 >> shift) as i64 - rate.0 as i64).abs();
+    let mut best = 0;
+    for shift in 1..=10 {
+        if error(shift) < error(best) {
+            best = shift;
+        }
+    }
+    best
+}
+
+impl<MODE> Rtc<MODE> {
+    /// Power up the RTC, reset it and start it counting in `mode`.
+    fn create(clock: &clock::RtcClock, rate: Hertz, rtc: RTC, pm: &mut PM, mode: u8) -> Self {
+        // this is safe because we're constrained to just the rtc bit
+        pm.apbamask.modify(|_, w| w.rtc_().set_bit());
+
+        rtc.mode0().ctrl.modify(|_, w| w.enable().clear_bit());
+        while rtc.mode0().status.read().syncbusy().bit_is_set() {}
+        rtc.mode0().ctrl.write(|w| w.swrst().set_bit());
+        while rtc.mode0().status.read().syncbusy().bit_is_set()
+            || rtc.mode0().ctrl.read().bits() & 1 != 0
+        {}
+
+        let shift = prescaler_shift(clock.freq(), rate);
+        rtc.mode0().ctrl.write(|w| unsafe {
+            w.mode().bits(mode);
+            w.prescaler().bits(shift)
+        });
+        while rtc.mode0().status.read().syncbusy().bit_is_set() {}
+
+        Self {
+            rtc,
+            freq: Hertz(clock.freq().0 >> shift),
+            _mode: PhantomData,
+        }
+    }
+
+    fn enable(&mut self) {
+        self.rtc.mode0().ctrl.modify(|_, w| w.enable().set_bit());
+        self.sync();
+    }
+
+    fn sync(&self) {
+        while self.rtc.mode0().status.read().syncbusy().bit_is_set() {}
+    }
+
+    /// Request that the counter value is synchronized so that it can
+    /// be read.
+    fn request_read(&self) {
+        self.rtc.mode0().readreq.write(|w| w.rreq().set_bit());
+        self.sync();
+    }
+
+    /// Returns the rate at which the counter ticks
+    pub fn freq(&self) -> Hertz {
+        self.freq
+    }
+
+    /// Apply a frequency correction to compensate for an inaccurate
+    /// oscillator.  Each step adjusts the rate by approximately 1ppm;
+    /// positive values speed the counter up and negative values slow
+    /// it down.  Values are clamped to the range -127 to 127.
+    pub fn set_frequency_correction(&mut self, ppm: i8) {
+        let value = (ppm as i16).abs().min(127) as u8;
+        self.rtc.mode0().freqcorr.write(|w| unsafe {
+            // The hardware treats a negative correction as speeding up
+            w.sign().bit(ppm > 0);
+            w.value().bits(value)
+        });
+        self.sync();
+    }
+
+    /// Disable the RTC and return the peripheral.
+    pub fn free(self) -> RTC {
+        self.rtc.mode0().ctrl.modify(|_, w| w.enable().clear_bit());
+        self.sync();
+        self.rtc
+    }
+}
+
+impl Rtc<Count32Mode> {
+    /// Configure the RTC as a 32-bit counter ticking as close as
+    /// possible to `rate`.
+    /// The clock is obtained from the `GenericClockController` instance.
+    pub fn count32_mode<T: Into<Hertz>>(
+        clock: &clock::RtcClock,
+        rate: T,
+        rtc: RTC,
+        pm: &mut PM,
+    ) -> Self {
+        let mut rtc = Self::create(clock, rate.into(), rtc, pm, 0);
+        rtc.enable();
+        rtc
+    }
+
+    /// Returns the current count
+    pub fn count(&self) -> u32 {
+        self.request_read();
+        self.rtc.mode0().count.read().count().bits()
+    }
+
+    /// Set the current count
+    pub fn set_count(&mut self, count: u32) {
+        self.rtc
+            .mode0()
+            .count
+            .write(|w| unsafe { w.count().bits(count) });
+        self.sync();
+    }
+
+    /// Set the value that triggers the compare interrupt when reached
+    /// by the counter.
+    pub fn set_compare(&mut self, value: u32) {
+        self.rtc.mode0().comp[0].write(|w| unsafe { w.comp().bits(value) });
+        self.sync();
+    }
+
+    /// Enable the interrupt generation for the compare value.
+    /// This method only sets the RTC configuration to trigger the
+    /// interrupt; it does not configure the interrupt controller or
+    /// define an interrupt handler.
+    pub fn enable_interrupt(&mut self) {
+        self.rtc.mode0().intenset.write(|w| w.cmp0().set_bit());
+    }
+
+    /// Disables interrupt generation for the compare value.
+    /// This method only sets the RTC configuration to prevent
+    /// triggering the interrupt; it does not configure the interrupt
+    /// controller.
+    pub fn disable_interrupt(&mut self) {
+        self.rtc.mode0().intenclr.write(|w| w.cmp0().set_bit());
+    }
+
+    /// Returns true if the counter has reached the compare value
+    pub fn is_interrupt(&self) -> bool {
+        self.rtc.mode0().intflag.read().cmp0().bit_is_set()
+    }
+
+    /// Clears the compare interrupt flag
+    pub fn clear_interrupt(&mut self) {
+        // Writing a 1 clears the flag
+        self.rtc.mode0().intflag.write(|w| w.cmp0().set_bit());
+    }
+}
+
+impl Periodic for Rtc<Count32Mode> {}
+impl CountDown for Rtc<Count32Mode> {
+    type Time = Hertz;
+
+    fn start<T>(&mut self, timeout: T)
+    where
+        T: Into<Hertz>,
+    {
+        let cycles = self.freq.0 / timeout.into().0.max(1);
+
+        // Clear the count on a compare match so that the timer repeats
+        self.rtc.mode0().ctrl.modify(|_, w| w.enable().clear_bit());
+        self.sync();
+        self.rtc.mode0().ctrl.modify(|_, w| w.matchclr().set_bit());
+        self.rtc.mode0().comp[0].write(|w| unsafe { w.comp().bits(cycles.max(1) - 1) });
+        self.rtc
+            .mode0()
+            .count
+            .write(|w| unsafe { w.count().bits(0) });
+        self.sync();
+        self.clear_interrupt();
+        self.enable();
+    }
+
+    fn wait(&mut self) -> nb::Result<(), Void> {
+        if self.is_interrupt() {
+            self.clear_interrupt();
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+impl Rtc<Count16Mode> {
+    /// Configure the RTC as a 16-bit counter ticking as close as
+    /// possible to `rate`, and wrapping after `period` + 1 ticks.
+    /// The clock is obtained from the `GenericClockController` instance.
+    pub fn count16_mode<T: Into<Hertz>>(
+        clock: &clock::RtcClock,
+        rate: T,
+        period: u16,
+        rtc: RTC,
+        pm: &mut PM,
+    ) -> Self {
+        let mut rtc = Self::create(clock, rate.into(), rtc, pm, 1);
+        rtc.rtc
+            .mode1()
+            .per
+            .write(|w| unsafe { w.per().bits(period) });
+        rtc.sync();
+        rtc.enable();
+        rtc
+    }
+
+    /// Returns the current count
+    pub fn count(&self) -> u16 {
+        self.request_read();
+        self.rtc.mode1().count.read().count().bits()
+    }
+
+    /// Set one of the two compare values.  The matching compare
+    /// flag is set when the counter reaches the value.
+    pub fn set_compare(&mut self, compare: Compare, value: u16) {
+        let index = match compare {
+            Compare::Compare0 => 0,
+            Compare::Compare1 => 1,
+        };
+        self.rtc.mode1().comp[index].write(|w| unsafe { w.comp().bits(value) });
+        self.sync();
+    }
+
+    /// Returns true if the counter has reached the `compare` value,
+    /// clearing the flag.
+    pub fn compare_matched(&mut self, compare: Compare) -> bool {
+        let flags = self.rtc.mode1().intflag.read();
+        let matched = match compare {
+            Compare::Compare0 => flags.cmp0().bit_is_set(),
+            Compare::Compare1 => flags.cmp1().bit_is_set(),
+        };
+        if matched {
+            // Writing a 1 clears the flag
+            self.rtc.mode1().intflag.write(|w| match compare {
+                Compare::Compare0 => w.cmp0().set_bit(),
+                Compare::Compare1 => w.cmp1().set_bit(),
+            });
+        }
+        matched
+    }
+
+    /// Enable the interrupt generation for each period.
+    /// This method only sets the RTC configuration to trigger the
+    /// interrupt; it does not configure the interrupt controller or
+    /// define an interrupt handler.
+    pub fn enable_interrupt(&mut self) {
+        self.rtc.mode1().intenset.write(|w| w.ovf().set_bit());
+    }
+
+    /// Disables interrupt generation for each period.
+    /// This method only sets the RTC configuration to prevent
+    /// triggering the interrupt; it does not configure the interrupt
+    /// controller.
+    pub fn disable_interrupt(&mut self) {
+        self.rtc.mode1().intenclr.write(|w| w.ovf().set_bit());
+    }
+
+    /// Returns true if the counter has wrapped
+    pub fn is_interrupt(&self) -> bool {
+        self.rtc.mode1().intflag.read().ovf().bit_is_set()
+    }
+
+    /// Clears the period interrupt flag
+    pub fn clear_interrupt(&mut self) {
+        // Writing a 1 clears the flag
+        self.rtc.mode1().intflag.write(|w| w.ovf().set_bit());
+    }
+}
+
+impl Rtc<ClockMode> {
+    /// Configure the RTC as a clock/calendar.  The prescaler closest
+    /// to producing a 1Hz tick is used; for accurate timekeeping the
+    /// clock must run at 1024Hz.
+    /// The clock is obtained from the `GenericClockController` instance.
+    pub fn clock_mode(clock: &clock::RtcClock, rtc: RTC, pm: &mut PM) -> Self {
+        let mut rtc = Self::create(clock, Hertz(1), rtc, pm, 2);
+        rtc.enable();
+        rtc
+    }
+
+    /// Returns the current date and time
+    pub fn current_time(&self) -> DateTime {
+        self.request_read();
+        let clock = self.rtc.mode2().clock.read();
+        DateTime {
+            year: BASE_YEAR + clock.year().bits() as u16,
+            month: clock.month().bits(),
+            day: clock.day().bits(),
+            hours: clock.hour().bits(),
+            minutes: clock.minute().bits(),
+            seconds: clock.second().bits(),
+        }
+    }
+
+    /// Set the current date and time.  Returns an error, leaving the
+    /// clock unchanged, if the year can't be represented.
+    pub fn set_time(&mut self, time: DateTime) -> Result<(), RtcError> {
+        let year = year_offset(time.year)?;
+        self.rtc.mode2().clock.write(|w| unsafe {
+            w.year().bits(year);
+            w.month().bits(time.month);
+            w.day().bits(time.day);
+            w.hour().bits(time.hours);
+            w.minute().bits(time.minutes);
+            w.second().bits(time.seconds)
+        });
+        self.sync();
+        Ok(())
+    }
+
+    /// Set the alarm to trigger when the fields of the current time
+    /// selected by `mask` match those of `time`.  Returns an error,
+    /// leaving the alarm unchanged, if the year can't be represented.
+    pub fn set_alarm(&mut self, time: DateTime, mask: AlarmMask) -> Result<(), RtcError> {
+        let year = year_offset(time.year)?;
+        self.rtc.mode2().alarm0.write(|w| unsafe {
+            w.year().bits(year);
+            w.month().bits(time.month);
+            w.day().bits(time.day);
+            w.hour().bits(time.hours);
+            w.minute().bits(time.minutes);
+            w.second().bits(time.seconds)
+        });
+        self.sync();
+        self.rtc.mode2().mask0.write(|w| w.sel().variant(mask));
+        self.sync();
+        Ok(())
+    }
+
+    /// Enable the interrupt generation for the alarm.
+    /// This method only sets the RTC configuration to trigger the
+    /// interrupt; it does not configure the interrupt controller or
+    /// define an interrupt handler.
+    pub fn enable_interrupt(&mut self) {
+        self.rtc.mode2().intenset.write(|w| w.alarm0().set_bit());
+    }
+
+    /// Disables interrupt generation for the alarm.
+    /// This method only sets the RTC configuration to prevent
+    /// triggering the interrupt; it does not configure the interrupt
+    /// controller.
+    pub fn disable_interrupt(&mut self) {
+        self.rtc.mode2().intenclr.write(|w| w.alarm0().set_bit());
+    }
+
+    /// Returns true if the alarm has triggered
+    pub fn is_interrupt(&self) -> bool {
+        self.rtc.mode2().intflag.read().alarm0().bit_is_set()
+    }
+
+    /// Clears the alarm interrupt flag
+    pub fn clear_interrupt(&mut self) {
+        // Writing a 1 clears the flag
+        self.rtc.mode2().intflag.write(|w| w.alarm0().set_bit());
+    }
+}
+
+impl Rtc<Count32Mode> {
+    /// Convert into a `Monotonic` clock counting from zero.
+    /// `core_freq` is the rate of the processor clock, from which
+    /// `StaticMonotonic::ratio` is computed.
+    pub fn into_monotonic<T>(mut self, core_freq: T) -> RtcMonotonic
+    where
+        T: Into<Hertz>,
+    {
+        self.rtc.mode0().ctrl.modify(|_, w| w.enable().clear_bit());
+        self.sync();
+        self.rtc
+            .mode0()
+            .ctrl
+            .modify(|_, w| w.matchclr().clear_bit());
+        self.set_count(0);
+        self.rtc.mode0().intflag.write(|w| w.ovf().set_bit());
+        self.enable();
+        // Keep the count synchronized so that it can be read without
+        // waiting for a read request each time
+        self.rtc.mode0().readreq.write(|w| {
+            w.rcont().set_bit();
+            w.rreq().set_bit()
+        });
+        self.sync();
+        let ratio = Fraction::of(core_freq.into(), self.freq);
+        interrupt::free(|_| unsafe {
+            MONOTONIC_OVERFLOWS = 0;
+            MONOTONIC_RATIO = ratio;
+        });
+        RtcMonotonic { rtc: self }
+    }
+}
+
+/// The overflow count of the `RtcMonotonic` clock, kept in a static
+/// so that the clock can be read through `StaticMonotonic`.
+static mut MONOTONIC_OVERFLOWS: u64 = 0;
+/// The ratio of the processor clock rate to the `RtcMonotonic` rate.
+static mut MONOTONIC_RATIO: Fraction = Fraction {
+    numerator: 1,
+    denominator: 1,
+};
+
+/// A `Monotonic` clock extending the 32-bit count of the RTC with a
+/// count of its overflows.  The clock keeps running in standby.
+pub struct RtcMonotonic {
+    rtc: Rtc<Count32Mode>,
+}
+
+impl RtcMonotonic {
+    /// Enable the interrupt generation for counter overflows, which
+    /// should call `now` to record the overflow.
+    /// This method only sets the RTC configuration to trigger the
+    /// interrupt; it does not configure the interrupt controller or
+    /// define an interrupt handler.
+    pub fn enable_interrupt(&mut self) {
+        self.rtc.rtc.mode0().intenset.write(|w| w.ovf().set_bit());
+    }
+
+    /// Disables interrupt generation for counter overflows.
+    /// This method only sets the RTC configuration to prevent
+    /// triggering the interrupt; it does not configure the interrupt
+    /// controller.
+    pub fn disable_interrupt(&mut self) {
+        self.rtc.rtc.mode0().intenclr.write(|w| w.ovf().set_bit());
+    }
+
+    /// Stop extending the count and return the RTC.
+    pub fn free(mut self) -> Rtc<Count32Mode> {
+        self.disable_interrupt();
+        self.rtc
+            .rtc
+            .mode0()
+            .readreq
+            .write(|w| w.rcont().clear_bit());
+        self.rtc
+    }
+}
+
+impl Monotonic for RtcMonotonic {
+    fn freq(&self) -> Hertz {
+        self.rtc.freq
+    }
+
+    fn now(&mut self) -> Instant {
+        <Self as StaticMonotonic>::now()
+    }
+}
+
+impl StaticMonotonic for RtcMonotonic {
+    type Instant = Instant;
+
+    fn ratio() -> Fraction {
+        interrupt::free(|_| unsafe { MONOTONIC_RATIO })
+    }
+
+    fn now() -> Instant {
+        // this is safe because the RtcMonotonic owns the RTC
+        let mode0 = unsafe { &*RTC::ptr() }.mode0();
+        interrupt::free(|_| {
+            let count = mode0.count.read().count().bits();
+            let overflows = unsafe { &mut MONOTONIC_OVERFLOWS };
+            let mut ticks = *overflows;
+            if mode0.intflag.read().ovf().bit_is_set() {
+                // Writing a 1 clears the flag
+                mode0.intflag.write(|w| w.ovf().set_bit());
+                *overflows = overflows.wrapping_add(1);
+                // A count in the lower half was read after the overflow
+                if count < 1 << 31 {
+                    ticks = ticks.wrapping_add(1);
+                }
+            }
+            Instant::from_ticks(ticks << 32 | count as u64)
+        })
+    }
+
+    unsafe fn reset() {
+        let mode0 = (*RTC::ptr()).mode0();
+        interrupt::free(|_| {
+            mode0.count.write(|w| w.count().bits(0));
+            while mode0.status.read().syncbusy().bit_is_set() {}
+            // Writing a 1 clears the flag
+            mode0.intflag.write(|w| w.ovf().set_bit());
+            MONOTONIC_OVERFLOWS = 0;
+        });
+    }
+
+    fn zero() -> Instant {
+        Instant::from_ticks(0)
+    }
+}
@@ -102,6 +102,23 @@ impl $Type {
         (self.sda, self.scl, self.sercom)
     }
 
+    /// Configure whether the I2C master keeps running in standby sleep
+    /// mode.  The SERCOM clock generator must also run in standby.
+    /// The bus is returned to the idle state afterwards.
+    pub fn run_in_standby(&mut self, enabled: bool) {
+        self.i2cm().ctrla.modify(|_, w| w.enable().clear_bit());
+        while self.i2cm().syncbusy.read().enable().bit_is_set() {}
+        self.i2cm().ctrla.modify(|_, w| {
+            w.runstdby().bit(enabled);
+            w.enable().set_bit()
+        });
+        while self.i2cm().syncbusy.read().enable().bit_is_set() {}
+        self.i2cm()
+            .status
+            .modify(|_, w| unsafe { w.busstate().bits(BUS_STATE_IDLE) });
+        while self.i2cm().syncbusy.read().sysop().bit_is_set() {}
+    }
+
     fn start_tx_write(&mut self, addr: u8) -> Result<(), I2CError> {
         loop {
             match self.i2cm().status.read().busstate().bits() {
@@ -1,4 +1,8 @@
 use clock;
+use dmac::{
+    BeatSize, Channel, ChannelId, Config, Priority, Transfer, TriggerAction, TriggerSource,
+    MAX_BEATS,
+};
 use hal::spi::{FullDuplex, Mode, Phase, Polarity};
 use nb;
 use sercom::pads::*;
@@ -13,6 +17,109 @@ pub enum Error {
     Overrun,
 }
 
+/// This is a helper trait used by the DMA transfer types to restore
+/// the SPI master to a usable state once a transfer has completed.
+/// It doesn't make sense to implement this trait outside of this module.
+pub trait SpiDma {
+    /// Wait for the final byte to be shifted out and discard any
+    /// data that was received but not collected by the DMAC.
+    fn finish_dma(&mut self);
+}
+
+/// A DMA driven write to an SPI master, created by the `send_dma`
+/// method.  Data received while sending is discarded.
+/// Completion can be detected by polling `is_done`, or by enabling the
+/// interrupt on the DMA channel prior to starting the transfer and
+/// calling `wait` from the DMAC interrupt handler.
+pub struct SpiSend<SPI, CH> {
+    spi: SPI,
+    transfer: Transfer<CH, &'static [u8]>,
+}
+
+impl<SPI, CH> SpiSend<SPI, CH>
+where
+    SPI: SpiDma,
+    CH: ChannelId,
+{
+    /// Returns true once all of the data has been handed to the SPI
+    /// hardware.
+    pub fn is_done(&self) -> bool {
+        self.transfer.is_done()
+    }
+
+    /// Block until the transfer has completed and return the SPI master,
+    /// DMA channel and buffer.  They are returned as an error if the
+    /// DMA transfer was aborted by a bus error.
+    pub fn wait(
+        self,
+    ) -> Result<(SPI, Channel<CH>, &'static [u8]), (SPI, Channel<CH>, &'static [u8])> {
+        let mut spi = self.spi;
+        match self.transfer.wait() {
+            Ok((channel, buffer)) => {
+                // Nothing was sent for an empty buffer
+                if !buffer.is_empty() {
+                    spi.finish_dma();
+                }
+                Ok((spi, channel, buffer))
+            }
+            Err((channel, buffer)) => Err((spi, channel, buffer)),
+        }
+    }
+}
+
+/// The SPI master, receive and transmit DMA channels and buffer that
+/// are returned by `SpiTransfer::wait`.
+pub type SpiTransferParts<SPI, RX, TX> = (SPI, Channel<RX>, Channel<TX>, &'static mut [u8]);
+
+/// A DMA driven full duplex transfer on an SPI master, created by the
+/// `transfer_dma` method.  The contents of the buffer are sent and
+/// replaced with the data received.
+/// Completion can be detected by polling `is_done`, or by enabling the
+/// interrupt on the receive DMA channel prior to starting the transfer
+/// and calling `wait` from the DMAC interrupt handler.
+pub struct SpiTransfer<SPI, RX, TX> {
+    spi: SPI,
+    rx: Transfer<RX, &'static mut [u8]>,
+    tx: Transfer<TX, ()>,
+}
+
+impl<SPI, RX, TX> SpiTransfer<SPI, RX, TX>
+where
+    SPI: SpiDma,
+    RX: ChannelId,
+    TX: ChannelId,
+{
+    /// Returns true once all of the data has been sent and received.
+    pub fn is_done(&self) -> bool {
+        self.rx.is_done() && self.tx.is_done()
+    }
+
+    /// Block until the transfer has completed and return the SPI master,
+    /// the receive and transmit DMA channels and the buffer.  They are
+    /// returned as an error if either DMA transfer was aborted by a bus
+    /// error.
+    pub fn wait(self) -> Result<SpiTransferParts<SPI, RX, TX>, SpiTransferParts<SPI, RX, TX>> {
+        let (tx_channel, tx_ok) = match self.tx.wait() {
+            Ok((channel, _)) => (channel, true),
+            Err((channel, _)) => (channel, false),
+        };
+        let (rx_channel, buffer, rx_ok) = match self.rx.wait() {
+            Ok((channel, buffer)) => (channel, buffer, true),
+            Err((channel, buffer)) => (channel, buffer, false),
+        };
+        let mut spi = self.spi;
+        if tx_ok && rx_ok {
+            // Nothing was sent for an empty buffer
+            if !buffer.is_empty() {
+                spi.finish_dma();
+            }
+            Ok((spi, rx_channel, tx_channel, buffer))
+        } else {
+            Err((spi, rx_channel, tx_channel, buffer))
+        }
+    }
+}
+
 macro_rules! spi_pinout {
     ([$($Type:ident:
         ($pad0:ident, $pad1:ident, $pad2:ident, $pad3:ident),)+
@@ -105,7 +212,8 @@ macro_rules! spi {
     ([
         $($Type:ident: (
                         $PinOut:ident,
-                        $SERCOM:ident, $powermask:ident, $clock:ident),)+
+                        $SERCOM:ident, $powermask:ident, $clock:ident,
+                        $rx_trigger:ident, $tx_trigger:ident),)+
     ]) => {
 $(
 
@@ -196,10 +304,126 @@ impl $Type {
         (self.pinout, self.sercom)
     }
 
+    /// Configure whether the SPI master keeps running in standby sleep
+    /// mode, allowing DMA transfers to complete while the CPU sleeps.
+    /// The SERCOM clock generator must also run in standby.
+    pub fn run_in_standby(&mut self, enabled: bool) {
+        let was_enabled = self.spi().ctrla.read().enable().bit_is_set();
+        self.spi().ctrla.modify(|_, w| w.enable().clear_bit());
+        while self.spi().syncbusy.read().enable().bit_is_set() {}
+        self.spi().ctrla.modify(|_, w| {
+            w.runstdby().bit(enabled);
+            w.enable().bit(was_enabled)
+        });
+        while self.spi().syncbusy.read().enable().bit_is_set() {}
+    }
+
     /// Helper for accessing the spi member of the sercom instance
     fn spi(&mut self) -> &SPI {
         &self.sercom.spi()
     }
+
+    /// Discard any received data that has not been read, along with
+    /// the overflow status, so that the receiver starts from a clean slate
+    fn discard_rx(&mut self) {
+        while self.spi().intflag.read().rxc().bit_is_set() {
+            self.spi().data.read();
+        }
+        // Writing a 1 clears the overflow status
+        self.spi().status.write(|w| w.bufovf().set_bit());
+    }
+
+    /// Returns the address of the DATA register for use by the DMAC
+    fn data_address(&mut self) -> *mut u8 {
+        &self.spi().data as *const _ as *mut u8
+    }
+
+    /// Write the contents of `buffer` to the bus using the DMAC,
+    /// leaving the CPU free to do other work in the meantime.
+    /// The SPI master and the DMA channel are held by the returned
+    /// `SpiSend` until the transfer completes.
+    /// Returns the SPI master, DMA channel and buffer as an error if
+    /// the buffer is longer than `MAX_BEATS`.
+    pub fn send_dma<CH: ChannelId>(
+        mut self,
+        channel: Channel<CH>,
+        buffer: &'static [u8],
+    ) -> Result<SpiSend<Self, CH>, (Self, Channel<CH>, &'static [u8])> {
+        self.discard_rx();
+        let data = self.data_address();
+        match unsafe {
+            channel.write_to(buffer, data, TriggerSource::$tx_trigger, Priority::Level0)
+        } {
+            Ok(transfer) => Ok(SpiSend {
+                spi: self,
+                transfer,
+            }),
+            Err((channel, buffer)) => Err((self, channel, buffer)),
+        }
+    }
+
+    /// Send the contents of `buffer` and replace it with the data
+    /// received from the bus, using one DMA channel for each direction.
+    /// The SPI master, the DMA channels and the buffer are held by the
+    /// returned `SpiTransfer` until the transfer completes.
+    /// Returns them as an error if the buffer is longer than `MAX_BEATS`.
+    pub fn transfer_dma<RX: ChannelId, TX: ChannelId>(
+        mut self,
+        rx_channel: Channel<RX>,
+        tx_channel: Channel<TX>,
+        buffer: &'static mut [u8],
+    ) -> Result<SpiTransfer<Self, RX, TX>, SpiTransferParts<Self, RX, TX>> {
+        if buffer.len() > MAX_BEATS {
+            return Err((self, rx_channel, tx_channel, buffer));
+        }
+        self.discard_rx();
+        let data = self.data_address();
+        let src = buffer.as_ptr() as u32;
+        let len = buffer.len();
+        let tx_config = Config {
+            beat_size: BeatSize::Byte,
+            src_increment: true,
+            dst_increment: false,
+            trigger: TriggerSource::$tx_trigger,
+            trigger_action: TriggerAction::Beat,
+            priority: Priority::Level0,
+        };
+        // Each byte is received only after it has been sent, so it is
+        // safe to receive into the same buffer that is being sent.
+        // The receive channel is started first and given the higher
+        // priority so that no incoming data is lost.
+        // The length has been checked above, so neither can fail.
+        let (rx, tx) = unsafe {
+            let rx = match rx_channel.read_from(
+                data as *const u8,
+                buffer,
+                TriggerSource::$rx_trigger,
+                Priority::Level1,
+            ) {
+                Ok(rx) => rx,
+                Err(_) => unreachable!(),
+            };
+            let tx = match tx_channel.start(tx_config, src, data as u32, len, ()) {
+                Ok(tx) => tx,
+                Err(_) => unreachable!(),
+            };
+            (rx, tx)
+        };
+        Ok(SpiTransfer {
+            spi: self,
+            rx,
+            tx,
+        })
+    }
+}
+
+impl SpiDma for $Type {
+    fn finish_dma(&mut self) {
+        // txc is transmit complete; it is set once the final byte has
+        // been shifted out and there is no further data waiting to be sent
+        while self.spi().intflag.read().txc().bit_is_clear() {}
+        self.discard_rx();
+    }
 }
 
 impl FullDuplex<u8> for $Type {
@@ -241,13 +465,19 @@ impl ::hal::blocking::spi::write::Default<u8> for $Type {}
 }
 
 spi!([
-    SPIMaster0: (SPI0Pinout, SERCOM0, sercom0_, Sercom0CoreClock),
-    SPIMaster1: (SPI1Pinout, SERCOM1, sercom1_, Sercom1CoreClock),
-    SPIMaster2: (SPI2Pinout, SERCOM2, sercom2_, Sercom2CoreClock),
-    SPIMaster3: (SPI3Pinout, SERCOM3, sercom3_, Sercom3CoreClock),
+    SPIMaster0: (SPI0Pinout, SERCOM0, sercom0_, Sercom0CoreClock,
+        Sercom0Rx, Sercom0Tx),
+    SPIMaster1: (SPI1Pinout, SERCOM1, sercom1_, Sercom1CoreClock,
+        Sercom1Rx, Sercom1Tx),
+    SPIMaster2: (SPI2Pinout, SERCOM2, sercom2_, Sercom2CoreClock,
+        Sercom2Rx, Sercom2Tx),
+    SPIMaster3: (SPI3Pinout, SERCOM3, sercom3_, Sercom3CoreClock,
+        Sercom3Rx, Sercom3Tx),
 ]);
 #[cfg(feature = "samd21g18a")]
 spi!([
-    SPIMaster4: (SPI4Pinout, SERCOM4, sercom4_, Sercom4CoreClock),
-    SPIMaster5: (SPI5Pinout, SERCOM5, sercom5_, Sercom5CoreClock),
+    SPIMaster4: (SPI4Pinout, SERCOM4, sercom4_, Sercom4CoreClock,
+        Sercom4Rx, Sercom4Tx),
+    SPIMaster5: (SPI5Pinout, SERCOM5, sercom5_, Sercom5CoreClock,
+        Sercom5Rx, Sercom5Tx),
 ]);
@@ -1,15 +1,150 @@
 use clock;
-use core::fmt;
+use core::{cmp, fmt, ptr};
+use dmac::{Channel, ChannelId, CircularTransfer, Priority, Transfer, TriggerSource};
 use hal::blocking::serial::{write::Default, Write};
 use hal::serial;
+use hal::timer::CountDown;
 use nb;
 use sercom::pads::*;
 use target_device::sercom0::USART;
-use target_device::Interrupt;
-use target_device::{NVIC, PM, SERCOM0, SERCOM1, SERCOM2, SERCOM3};
+use target_device::{PM, SERCOM0, SERCOM1, SERCOM2, SERCOM3};
 #[cfg(feature = "samd21g18a")]
 use target_device::{SERCOM4, SERCOM5};
 use time::Hertz;
+use void::Void;
+
+/// This is a helper trait used by the DMA types to restore the UART
+/// to a usable state once a transfer has completed.
+/// It doesn't make sense to implement this trait outside of this module.
+pub trait UartDma {
+    /// Wait for the final character to be shifted out.
+    fn finish_dma(&mut self);
+}
+
+/// A DMA driven write to a UART, created by the `write_dma` method.
+/// Completion can be detected by polling `is_done`, or by enabling the
+/// interrupt on the DMA channel prior to starting the transfer and
+/// calling `wait` from the DMAC interrupt handler.
+pub struct UartSend<UART, CH> {
+    uart: UART,
+    transfer: Transfer<CH, &'static [u8]>,
+}
+
+impl<UART, CH> UartSend<UART, CH>
+where
+    UART: UartDma,
+    CH: ChannelId,
+{
+    /// Returns true once all of the data has been handed to the UART
+    /// hardware.
+    pub fn is_done(&self) -> bool {
+        self.transfer.is_done()
+    }
+
+    /// Block until the transfer has completed and return the UART,
+    /// DMA channel and buffer.  They are returned as an error if the
+    /// DMA transfer was aborted by a bus error.
+    pub fn wait(
+        self,
+    ) -> Result<(UART, Channel<CH>, &'static [u8]), (UART, Channel<CH>, &'static [u8])> {
+        let mut uart = self.uart;
+        match self.transfer.wait() {
+            Ok((channel, buffer)) => {
+                // Nothing was sent for an empty buffer
+                if !buffer.is_empty() {
+                    uart.finish_dma();
+                }
+                Ok((uart, channel, buffer))
+            }
+            Err((channel, buffer)) => Err((uart, channel, buffer)),
+        }
+    }
+}
+
+/// A UART receiving continuously into a ring buffer via DMA, created
+/// by the `into_dma_rx` method.
+/// The buffer is filled without any CPU involvement; `read` copies out
+/// the data that has arrived.  A completely full buffer can't be told
+/// apart from an empty one, so at most `capacity` bytes, one less than
+/// the length of the buffer, can be held between calls to `read`; any
+/// further data overwrites the oldest bytes and is lost.
+/// The companion timer is used to detect when the line has gone idle
+/// so that a partially filled frame can be processed without waiting
+/// for further data to arrive.
+pub struct UartDmaRx<UART, CH, TIM> {
+    uart: UART,
+    transfer: CircularTransfer<CH, &'static mut [u8]>,
+    timer: TIM,
+    timeout: Hertz,
+    read_pos: usize,
+    idle_pos: usize,
+}
+
+impl<UART, CH, TIM> UartDmaRx<UART, CH, TIM>
+where
+    CH: ChannelId,
+    TIM: CountDown<Time = Hertz>,
+{
+    fn len(&self) -> usize {
+        self.transfer.payload().len()
+    }
+
+    /// Returns the number of bytes that can be held between calls to
+    /// `read` without losing data.
+    pub fn capacity(&self) -> usize {
+        self.len() - 1
+    }
+
+    /// Returns the number of bytes that have been received but not
+    /// yet collected via `read`.
+    pub fn available(&self) -> usize {
+        let len = self.len();
+        (self.transfer.position() + len - self.read_pos) % len
+    }
+
+    /// Copy as many of the received bytes as fit into `buffer`,
+    /// returning the number of bytes copied.
+    pub fn read(&mut self, buffer: &mut [u8]) -> usize {
+        let len = self.len();
+        let count = cmp::min(self.available(), buffer.len());
+        let data = self.transfer.payload().as_ptr();
+        for byte in buffer[..count].iter_mut() {
+            // The DMAC writes to the buffer behind our back, so the
+            // reads must not be elided or reordered
+            *byte = unsafe { ptr::read_volatile(data.offset(self.read_pos as isize)) };
+            self.read_pos = (self.read_pos + 1) % len;
+        }
+        count
+    }
+
+    /// Check whether the line has gone idle.
+    /// This is intended to be called regularly, for example from the
+    /// timer interrupt handler.  Returns the number of bytes pending
+    /// once the timer has expired without any further data arriving,
+    /// and `WouldBlock` otherwise.
+    pub fn poll_idle(&mut self) -> nb::Result<usize, Void> {
+        self.timer.wait()?;
+        self.timer.start(self.timeout);
+
+        let pos = self.transfer.position();
+        if pos != self.idle_pos {
+            // Data is still arriving
+            self.idle_pos = pos;
+            return Err(nb::Error::WouldBlock);
+        }
+
+        match self.available() {
+            0 => Err(nb::Error::WouldBlock),
+            pending => Ok(pending),
+        }
+    }
+
+    /// Stop receiving and return the UART, DMA channel, buffer and timer.
+    pub fn stop(self) -> (UART, Channel<CH>, &'static mut [u8], TIM) {
+        let (channel, buffer) = self.transfer.stop();
+        (self.uart, channel, buffer, self.timer)
+    }
+}
 
 macro_rules! uart_pinout {
     ([$($Type:ident:
@@ -74,13 +209,16 @@ macro_rules! uart {
                         $pinout:ident,
                         $SERCOM:ident,
                         $powermask:ident,
-                        $clock:ident),)+
+                        $clock:ident,
+                        $rx_trigger:ident,
+                        $tx_trigger:ident),)+
     ]) => {
 $(
 
 pub struct $Type {
     _pinout: $pinout,
     sercom: $SERCOM,
+    baud: Hertz,
 }
 
 impl $Type {
@@ -88,10 +226,10 @@ impl $Type {
         clock: &clock::$clock,
         freq: F,
         sercom: $SERCOM,
-        nvic: &mut NVIC,
         pm: &mut PM,
         pinout: $pinout
     ) -> $Type {
+        let baud = freq.into();
         pm.apbcmask.modify(|_, w| w.$powermask().set_bit());
 
         // Lots of union fields which require unsafe access
@@ -113,7 +251,6 @@ impl $Type {
 
                 w.form().bits(0x00);
                 w.sampr().bits(0x00); // 16x oversample fractional
-                w.runstdby().set_bit(); // Run in standby
                 w.form().bits(0); // 0 is no parity bits
 
                 w.mode().usart_int_clk() // Internal clock mode
@@ -135,7 +272,7 @@ impl $Type {
 //            });
 
             // Asynchronous arithmetic mode (Table 24-2 in datasheet)
-            let baud = calculate_baud_value(freq.into().0, fref, sample_rate);
+            let baud = calculate_baud_value(baud.0, fref, sample_rate);
 
             sercom.usart().baud().modify(|_, w| {
                 w.baud().bits(baud)
@@ -150,14 +287,6 @@ impl $Type {
 
             while sercom.usart().syncbusy.read().ctrlb().bit_is_set() {}
 
-            nvic.enable(Interrupt::$SERCOM);
-
-            sercom.usart().intenset.modify(|_, w| {
-                w.rxc().set_bit()
-                //w.txc().set_bit()
-                //w.dre().set_bit()
-            });
-
             sercom.usart().ctrla.modify(|_, w| w.enable().set_bit());
             // wait for sync of ENABLE
             while sercom.usart().syncbusy.read().enable().bit_is_set() {}
@@ -166,6 +295,7 @@ impl $Type {
         Self {
             _pinout: pinout,
             sercom,
+            baud,
         }
     }
 
@@ -176,6 +306,118 @@ impl $Type {
     fn dre(&self) -> bool {
         self.usart().intflag.read().dre().bit_is_set()
     }
+
+    /// Enable the receive complete interrupt.
+    /// This method only sets the UART configuration to trigger
+    /// the interrupt; it does not configure the interrupt controller
+    /// or define an interrupt handler.
+    pub fn enable_rx_interrupt(&mut self) {
+        self.usart().intenset.write(|w| w.rxc().set_bit());
+    }
+
+    /// Disables the receive complete interrupt.
+    pub fn disable_rx_interrupt(&mut self) {
+        self.usart().intenclr.write(|w| w.rxc().set_bit());
+    }
+
+    /// Configure whether the UART keeps running in standby sleep mode,
+    /// so that received data can wake the device.  The SERCOM clock
+    /// generator must also run in standby.
+    pub fn run_in_standby(&mut self, enabled: bool) {
+        let was_enabled = self.usart().ctrla.read().enable().bit_is_set();
+        self.usart().ctrla.modify(|_, w| w.enable().clear_bit());
+        while self.usart().syncbusy.read().enable().bit_is_set() {}
+        self.usart().ctrla.modify(|_, w| {
+            w.runstdby().bit(enabled);
+            w.enable().bit(was_enabled)
+        });
+        while self.usart().syncbusy.read().enable().bit_is_set() {}
+    }
+
+    fn discard_rx(&mut self) {
+        while self.usart().intflag.read().rxc().bit_is_set() {
+            self.usart().data.read();
+        }
+    }
+
+    fn data_address(&mut self) -> *mut u8 {
+        &self.usart().data as *const _ as *mut u8
+    }
+
+    /// Send the contents of `buffer` using the DMAC.  The UART and
+    /// buffer are held by the returned `UartSend` until the transfer
+    /// completes.
+    /// Returns the UART, DMA channel and buffer as an error if the
+    /// buffer is longer than `MAX_BEATS`.
+    pub fn write_dma<CH: ChannelId>(
+        mut self,
+        channel: Channel<CH>,
+        buffer: &'static [u8],
+    ) -> Result<UartSend<Self, CH>, (Self, Channel<CH>, &'static [u8])> {
+        let data = self.data_address();
+        match unsafe {
+            channel.write_to(buffer, data, TriggerSource::$tx_trigger, Priority::Level0)
+        } {
+            Ok(transfer) => Ok(UartSend {
+                uart: self,
+                transfer,
+            }),
+            Err((channel, buffer)) => Err((self, channel, buffer)),
+        }
+    }
+
+    /// Receive continuously into `buffer` using the DMAC, wrapping
+    /// around to the start once the end of the buffer is reached.
+    /// `timer` is started with a period of `idle_chars` character times
+    /// at the configured baud rate and is used by `UartDmaRx::poll_idle`
+    /// to detect when the line has gone idle.
+    /// Returns the UART, DMA channel, buffer and timer as an error if
+    /// the buffer is shorter than two bytes or longer than `MAX_BEATS`.
+    pub fn into_dma_rx<CH, TIM>(
+        mut self,
+        channel: Channel<CH>,
+        buffer: &'static mut [u8],
+        mut timer: TIM,
+        idle_chars: u32,
+    ) -> Result<UartDmaRx<Self, CH, TIM>, (Self, Channel<CH>, &'static mut [u8], TIM)>
+    where
+        CH: ChannelId,
+        TIM: CountDown<Time = Hertz>,
+    {
+        // A character is 10 bits long; a start bit, 8 data bits and a
+        // stop bit
+        let timeout = Hertz(cmp::max(self.baud.0 / (10 * cmp::max(idle_chars, 1)), 1));
+        // One byte of the buffer is always left unused
+        if buffer.len() < 2 {
+            return Err((self, channel, buffer, timer));
+        }
+
+        self.discard_rx();
+        let data = self.data_address();
+        let transfer = match unsafe {
+            channel.read_from_circular(data, buffer, TriggerSource::$rx_trigger, Priority::Level1)
+        } {
+            Ok(transfer) => transfer,
+            Err((channel, buffer)) => return Err((self, channel, buffer, timer)),
+        };
+        timer.start(timeout);
+        Ok(UartDmaRx {
+            uart: self,
+            transfer,
+            timer,
+            timeout,
+            read_pos: 0,
+            idle_pos: 0,
+        })
+    }
+}
+
+impl UartDma for $Type {
+    fn finish_dma(&mut self) {
+        // txc is transmit complete; it is set once the final character
+        // has been shifted out and there is no further data waiting to be sent
+        while self.usart().intflag.read().txc().bit_is_clear() {}
+    }
 }
 
 
@@ -236,16 +478,16 @@ impl fmt::Write for $Type {
 }
 
 uart!([
-    UART0: (UART0Pinout, SERCOM0, sercom0_, Sercom0CoreClock),
-    UART1: (UART1Pinout, SERCOM1, sercom1_, Sercom1CoreClock),
-    UART2: (UART2Pinout, SERCOM2, sercom2_, Sercom2CoreClock),
-    UART3: (UART3Pinout, SERCOM3, sercom3_, Sercom3CoreClock),
+    UART0: (UART0Pinout, SERCOM0, sercom0_, Sercom0CoreClock, Sercom0Rx, Sercom0Tx),
+    UART1: (UART1Pinout, SERCOM1, sercom1_, Sercom1CoreClock, Sercom1Rx, Sercom1Tx),
+    UART2: (UART2Pinout, SERCOM2, sercom2_, Sercom2CoreClock, Sercom2Rx, Sercom2Tx),
+    UART3: (UART3Pinout, SERCOM3, sercom3_, Sercom3CoreClock, Sercom3Rx, Sercom3Tx),
 ]);
 
 #[cfg(feature = "samd21g18a")]
 uart!([
-    UART4: (UART4Pinout, SERCOM4, sercom4_, Sercom4CoreClock),
-    UART5: (UART5Pinout, SERCOM5, sercom5_, Sercom5CoreClock),
+    UART4: (UART4Pinout, SERCOM4, sercom4_, Sercom4CoreClock, Sercom4Rx, Sercom4Tx),
+    UART5: (UART5Pinout, SERCOM5, sercom5_, Sercom5CoreClock, Sercom5Rx, Sercom5Tx),
 ]);
 
 const SHIFT: u8 = 32;
@@ -0,0 +1,48 @@
+//! Entering the low power sleep modes.
+//! In the idle modes the CPU is halted along with progressively more
+//! of the bus clocks, while the peripherals keep running.  In standby
+//! the clock sources and generic clocks are also stopped, except for
+//! those configured to run in standby via
+//! `GenericClockController::configure_standby` and
+//! `GenericClockController::configure_source_standby`; peripherals
+//! keep running only when their `run_in_standby` option is set.
+//! Any enabled interrupt wakes the device.
+use cortex_m::asm;
+use cortex_m::peripheral::SCB;
+use target_device::PM;
+
+/// The SLEEPDEEP bit of the System Control Register
+const SCR_SLEEPDEEP: u32 = 1 << 2;
+
+/// The clocks stopped by the idle sleep modes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IdleLevel {
+    /// The CPU clock is stopped
+    Idle0,
+    /// The CPU and AHB clocks are stopped
+    Idle1,
+    /// The CPU, AHB and APB clocks are stopped
+    Idle2,
+}
+
+/// Sleep in idle mode until an interrupt occurs.
+pub fn idle(pm: &mut PM, scb: &mut SCB, level: IdleLevel) {
+    pm.sleep.write(|w| match level {
+        IdleLevel::Idle0 => w.idle().cpu(),
+        IdleLevel::Idle1 => w.idle().ahb(),
+        IdleLevel::Idle2 => w.idle().apb(),
+    });
+    // this is safe because we're constrained to just the sleepdeep bit
+    unsafe { scb.scr.modify(|scr| scr & !SCR_SLEEPDEEP) };
+    asm::dsb();
+    asm::wfi();
+}
+
+/// Sleep in standby mode until an interrupt occurs.
+pub fn standby(scb: &mut SCB) {
+    // this is safe because we're constrained to just the sleepdeep bit
+    unsafe { scb.scr.modify(|scr| scr | SCR_SLEEPDEEP) };
+    asm::dsb();
+    asm::wfi();
+    unsafe { scb.scr.modify(|scr| scr & !SCR_SLEEPDEEP) };
+}
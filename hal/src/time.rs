@@ -1,4 +1,5 @@
 //! Time units
+use core::ops::{Add, Sub};
 
 /// Bits per second
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -16,6 +17,10 @@ pub struct KiloHertz(pub u32);
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct MegaHertz(pub u32);
 
+/// Milliseconds
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Milliseconds(pub u32);
+
 /// Extension trait that adds convenience methods to the `u32` type
 pub trait U32Ext {
     /// Wrap in `Bps`
@@ -29,6 +34,9 @@ pub trait U32Ext {
 
     /// Wrap in `MegaHertz`
     fn mhz(self) -> MegaHertz;
+
+    /// Wrap in `Milliseconds`
+    fn ms(self) -> Milliseconds;
 }
 
 impl U32Ext for u32 {
@@ -47,6 +55,10 @@ impl U32Ext for u32 {
     fn mhz(self) -> MegaHertz {
         MegaHertz(self)
     }
+
+    fn ms(self) -> Milliseconds {
+        Milliseconds(self)
+    }
 }
 
 impl Into<Hertz> for KiloHertz {
@@ -66,3 +78,175 @@ impl Into<KiloHertz> for MegaHertz {
         KiloHertz(self.0 * 1_000)
     }
 }
+
+/// A point in time, measured in ticks of a `Monotonic` clock since the
+/// clock was started.  The 64-bit tick count doesn't wrap in practice,
+/// so instants can be compared directly.  Arithmetic on instants
+/// saturates at the ends of the tick range rather than wrapping or
+/// panicking; `checked_add` and `checked_sub` report overflow instead.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// Create an instant from a tick count
+    pub fn from_ticks(ticks: u64) -> Self {
+        Instant(ticks)
+    }
+
+    /// Returns the number of ticks since the clock was started
+    pub fn ticks(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns the time elapsed from `earlier` to this instant, or
+    /// zero if `earlier` is later than this instant.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration(self.0.saturating_sub(earlier.0))
+    }
+
+    /// Returns the instant `duration` after this one, or `None` if it
+    /// overflows the tick count.
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_add(duration.0).map(Instant)
+    }
+
+    /// Returns the instant `duration` before this one, or `None` if it
+    /// would be before the clock was started.
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_sub(duration.0).map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        Instant(self.0.saturating_add(duration.0))
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, duration: Duration) -> Instant {
+        Instant(self.0.saturating_sub(duration.0))
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// A span of time, measured in ticks of a `Monotonic` clock.  The
+/// `Monotonic` trait converts between durations and real time units.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Duration(u64);
+
+impl Duration {
+    /// Create a duration from a tick count
+    pub fn from_ticks(ticks: u64) -> Self {
+        Duration(ticks)
+    }
+
+    /// Returns the number of ticks in the duration
+    pub fn ticks(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, other: Duration) -> Duration {
+        Duration(self.0 + other.0)
+    }
+}
+
+impl Sub for Duration {
+    type Output = Duration;
+
+    fn sub(self, other: Duration) -> Duration {
+        Duration(self.0.saturating_sub(other.0))
+    }
+}
+
+/// A clock that never goes backwards, suitable for timestamps and for
+/// scheduling.  Implementations extend a hardware counter with an
+/// overflow count, so `now` must be called at least once per wrap of
+/// the hardware counter; calling it from the overflow interrupt
+/// handler guarantees this.
+pub trait Monotonic {
+    /// Returns the rate at which the clock ticks
+    fn freq(&self) -> Hertz;
+
+    /// Returns the current time
+    fn now(&mut self) -> Instant;
+
+    /// Convert a number of milliseconds to a duration of this clock
+    fn millis(&self, ms: u32) -> Duration {
+        Duration(ms as u64 * self.freq().0 as u64 / 1_000)
+    }
+
+    /// Convert a number of microseconds to a duration of this clock
+    fn micros(&self, us: u32) -> Duration {
+        Duration(us as u64 * self.freq().0 as u64 / 1_000_000)
+    }
+
+    /// Convert a duration of this clock to microseconds
+    fn as_micros(&self, duration: Duration) -> u64 {
+        duration.0 * 1_000_000 / self.freq().0 as u64
+    }
+}
+
+/// The ratio between two clock rates, as returned by
+/// `StaticMonotonic::ratio`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Fraction {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+impl Fraction {
+    /// Returns the ratio of `numerator` to `denominator` in its lowest
+    /// terms.
+    pub fn of(numerator: Hertz, denominator: Hertz) -> Self {
+        let (mut a, mut b) = (numerator.0, denominator.0);
+        while b != 0 {
+            let rem = a % b;
+            a = b;
+            b = rem;
+        }
+        let gcd = a.max(1);
+        Fraction {
+            numerator: numerator.0 / gcd,
+            denominator: denominator.0 / gcd,
+        }
+    }
+}
+
+/// A `Monotonic` clock that can be read without a reference to it.
+/// The methods match those of the `Monotonic` trait of cortex-m-rtfm,
+/// so an application can back `#[app(monotonic = ..)]` by implementing
+/// that trait for a local type that forwards to these methods.  The
+/// methods must only be used while the clock exists.
+pub trait StaticMonotonic {
+    /// The type of the timestamps produced by the clock
+    type Instant: Copy + Ord + Sub;
+
+    /// Returns the ratio of the processor clock rate to the rate at
+    /// which this clock ticks
+    fn ratio() -> Fraction;
+
+    /// Returns the current time
+    fn now() -> Self::Instant;
+
+    /// Restart the clock from zero
+    unsafe fn reset();
+
+    /// Returns the instant at which the clock starts
+    fn zero() -> Self::Instant;
+}
@@ -1,24 +1,42 @@
 //! Working with timer counter hardware
 use hal::timer::{CountDown, Periodic};
-use target_device::tc3::COUNT16;
+use target_device::tc3::{COUNT16, COUNT32, COUNT8};
 #[allow(unused)]
 use target_device::{PM, TC3, TC4, TC5};
 
 use clock;
+use cortex_m::interrupt;
+use evsys::{ConnectedUser, Tc3User, Tc4User, Tc5User};
 use nb;
-use time::Hertz;
+use time::{Fraction, Hertz, Instant, Monotonic, StaticMonotonic};
 use void::Void;
 
 // Note:
-// TC3 + TC4 can be paired to make a 32-bit counter
-// TC5 + TC6 can be paired to make a 32-bit counter
+// TC4 + TC5 can be paired to make a 32-bit counter
+// TC6 + TC7 can be paired on the larger parts, which
+// aren't currently supported by this crate
+
+/// Set RUNSTDBY on a CTRLA register, briefly disabling the timer
+/// because the bit is enable-protected.  This is a macro because each
+/// counter mode has its own register type.
+macro_rules! set_run_in_standby {
+    ($count:expr, $enabled:expr) => {{
+        let count = $count;
+        let was_enabled = count.ctrla.read().enable().bit_is_set();
+        count.ctrla.modify(|_, w| w.enable().clear_bit());
+        while count.status.read().syncbusy().bit_is_set() {}
+        count.ctrla.modify(|_, w| {
+            w.runstdby().bit($enabled);
+            w.enable().bit(was_enabled)
+        });
+        while count.status.read().syncbusy().bit_is_set() {}
+    }};
+}
 
 /// A generic hardware timer counter.
-/// The counters are exposed in 16-bit mode only.
-/// The hardware allows configuring the 8-bit mode
-/// and pairing up some instances to run in 32-bit
-/// mode, but that functionality is not currently
-/// exposed by this hal implementation.
+/// The counters are exposed in 16-bit mode; they can be
+/// switched to 8-bit mode via `into_8bit`, and pairs of
+/// instances can run in 32-bit mode via `TimerCounter32`.
 /// TimerCounter implements both the `Periodic` and
 /// the `CountDown` embedded_hal timer traits.
 /// Before a hardware timer can be used, it must first
@@ -35,6 +53,11 @@ pub trait Count16 {
     fn count_16(&self) -> &COUNT16;
 }
 
+/// The 8-bit counterpart of `Count16`.
+pub trait Count8 {
+    fn count_8(&self) -> &COUNT8;
+}
+
 impl<TC> Periodic for TimerCounter<TC> {}
 impl<TC> CountDown for TimerCounter<TC>
 where
@@ -42,27 +65,603 @@ where
 {
     type Time = Hertz;
 
+    /// Start the timer.  This panics if the timeout can't be reached;
+    /// `try_start` reports the error instead.
     fn start<T>(&mut self, timeout: T)
     where
         T: Into<Hertz>,
     {
-        let timeout = timeout.into();
-        let params = clock::ClockParams::new(self.freq, timeout);
-        let divider = params.divider;
+        if let Err(err) = self.try_start(timeout) {
+            panic!("timer can't be started: {:?}", err);
+        }
+    }
+
+    fn wait(&mut self) -> nb::Result<(), Void> {
         let count = self.tc.count_16();
+        if count.intflag.read().ovf().bit_is_set() {
+            // Writing a 1 clears the flag
+            count.intflag.modify(|_, w| w.ovf().set_bit());
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+/// The error returned when a timer can't generate the requested
+/// timeout frequency.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimerError {
+    /// The requested frequency is higher than the `max` that the timer
+    /// can generate from its clock.
+    FrequencyTooHigh { requested: Hertz, max: Hertz },
+    /// The requested frequency is lower than the `min` that the timer
+    /// can generate from its clock; a wider counter may be able to
+    /// reach it.
+    FrequencyTooLow { requested: Hertz, min: Hertz },
+    /// The requested frequency is within the range of the timer, but
+    /// falls between the frequencies that its prescalers and counter
+    /// width can generate.
+    FrequencyUnreachable { requested: Hertz },
+}
+
+/// Compute the prescaler and TOP value for `timeout`, or describe why
+/// it can't be reached.
+pub(crate) fn counter_params(
+    src_freq: Hertz,
+    timeout: Hertz,
+    max_top: u32,
+) -> Result<clock::CounterParams, TimerError> {
+    clock::CounterParams::new(src_freq, timeout, max_top).ok_or_else(|| {
+        // The slowest rate uses the largest prescaler and TOP value
+        let cycles = 1024 * (max_top as u64 + 1);
+        let min = (src_freq.0 as u64 + cycles - 1) / cycles;
+        if timeout.0 > src_freq.0 {
+            TimerError::FrequencyTooHigh {
+                requested: timeout,
+                max: src_freq,
+            }
+        } else if (timeout.0 as u64) < min {
+            TimerError::FrequencyTooLow {
+                requested: timeout,
+                min: Hertz(min as u32),
+            }
+        } else {
+            TimerError::FrequencyUnreachable { requested: timeout }
+        }
+    })
+}
+
+/// Select the prescaler for a counter that runs freely at close to
+/// `rate`, or describe why it can't be reached.
+fn tick_params(src_freq: Hertz, rate: Hertz) -> Result<clock::CounterParams, TimerError> {
+    clock::CounterParams::prescaled(src_freq, rate).ok_or_else(|| {
+        if rate.0 > src_freq.0 {
+            TimerError::FrequencyTooHigh {
+                requested: rate,
+                max: src_freq,
+            }
+        } else {
+            // The slowest rate uses the largest prescaler
+            TimerError::FrequencyTooLow {
+                requested: rate,
+                min: Hertz((src_freq.0 + 1023) / 1024),
+            }
+        }
+    })
+}
+
+/// Reset the timer and start it counting towards `timeout`.  In
+/// one-shot mode the timer stops when it expires.
+fn start_16(count: &COUNT16, freq: Hertz, timeout: Hertz, oneshot: bool) -> Result<(), TimerError> {
+    // TimerCounter32 can handle the longer timeouts
+    let params = counter_params(freq, timeout, u16::max_value() as u32)?;
+
+    reset(count);
+
+    count.ctrlbset.write(|w| {
+        // Count up when the direction bit is zero
+        w.dir().clear_bit();
+        w.oneshot().bit(oneshot)
+    });
+
+    // Set TOP value for mfrq mode
+    count.cc[0].write(|w| unsafe { w.cc().bits(params.top as u16) });
+
+    count.ctrla.modify(|_, w| {
+        set_prescaler!(w, params.divider);
+        // Enable Match Frequency Waveform generation
+        w.wavegen().mfrq();
+        w.enable().set_bit()
+    });
+    Ok(())
+}
+
+/// Disable the timer and perform a software reset, returning all
+/// of its registers other than the standby setting to their default
+/// values
+fn reset(count: &COUNT16) {
+    let standby = count.ctrla.read().runstdby().bit_is_set();
+    // Disable the timer while we reconfigure it
+    count.ctrla.modify(|_, w| w.enable().clear_bit());
+    while count.status.read().syncbusy().bit_is_set() {}
+
+    // Now that we have a clock routed to the peripheral, we
+    // can ask it to perform a reset.
+    count.ctrla.write(|w| w.swrst().set_bit());
+    while count.status.read().syncbusy().bit_is_set() {}
+    // the SVD erroneously marks swrst as write-only, so we
+    // need to manually read the bit here
+    while count.ctrla.read().bits() & 1 != 0 {}
+    // Keep the setting made by `run_in_standby`
+    count.ctrla.write(|w| w.runstdby().bit(standby));
+}
+
+impl<TC> TimerCounter<TC>
+where
+    TC: Count16,
+{
+    /// Start the timer, or return an error if `timeout` can't be
+    /// generated from the timer's clock.
+    pub fn try_start<T>(&mut self, timeout: T) -> Result<(), TimerError>
+    where
+        T: Into<Hertz>,
+    {
+        start_16(self.tc.count_16(), self.freq, timeout.into(), false)
+    }
+
+    /// Enable the interrupt generation for this hardware timer.
+    /// This method only sets the clock configuration to trigger
+    /// the interrupt; it does not configure the interrupt controller
+    /// or define an interrupt handler.
+    pub fn enable_interrupt(&mut self) {
+        self.tc.count_16().intenset.write(|w| w.ovf().set_bit());
+    }
+
+    /// Disables interrupt generation for this hardware timer.
+    /// This method only sets the clock configuration to prevent
+    /// triggering the interrupt; it does not configure the interrupt
+    /// controller.
+    pub fn disable_interrupt(&mut self) {
+        self.tc.count_16().intenclr.write(|w| w.ovf().set_bit());
+    }
+
+    /// Configure whether the timer keeps counting in standby sleep
+    /// mode, so that it can wake the device.  The timer's clock
+    /// generator must also run in standby.
+    pub fn run_in_standby(&mut self, enabled: bool) {
+        set_run_in_standby!(self.tc.count_16(), enabled);
+    }
+
+    /// Convert into a timer that stops after expiring once, rather
+    /// than repeating.
+    pub fn into_one_shot(self) -> OneShotTimer<TC> {
+        OneShotTimer {
+            freq: self.freq,
+            tc: self.tc,
+        }
+    }
+}
+
+impl<TC> TimerCounter<TC>
+where
+    TC: Count16 + Count8,
+{
+    /// Convert into a timer using the 8-bit counter mode.
+    pub fn into_8bit(self) -> TimerCounter8<TC> {
+        reset(self.tc.count_16());
+        TimerCounter8 {
+            freq: self.freq,
+            tc: self.tc,
+        }
+    }
+}
+
+/// A timer counter that expires once each time it is started.
+/// After expiring, the timer can be restarted with the same timeout
+/// by `retrigger`, without reprogramming it.
+/// OneShotTimer implements the `CountDown` embedded_hal timer trait.
+pub struct OneShotTimer<TC> {
+    freq: Hertz,
+    tc: TC,
+}
+
+impl<TC> CountDown for OneShotTimer<TC>
+where
+    TC: Count16,
+{
+    type Time = Hertz;
+
+    /// Start the timer.  This panics if the timeout can't be reached;
+    /// `try_start` reports the error instead.
+    fn start<T>(&mut self, timeout: T)
+    where
+        T: Into<Hertz>,
+    {
+        if let Err(err) = self.try_start(timeout) {
+            panic!("timer can't be started: {:?}", err);
+        }
+    }
+
+    fn wait(&mut self) -> nb::Result<(), Void> {
+        let count = self.tc.count_16();
+        if count.intflag.read().ovf().bit_is_set() {
+            // Writing a 1 clears the flag
+            count.intflag.write(|w| w.ovf().set_bit());
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+impl<TC> OneShotTimer<TC>
+where
+    TC: Count16,
+{
+    /// Start the timer, or return an error if `timeout` can't be
+    /// generated from the timer's clock.
+    pub fn try_start<T>(&mut self, timeout: T) -> Result<(), TimerError>
+    where
+        T: Into<Hertz>,
+    {
+        start_16(self.tc.count_16(), self.freq, timeout.into(), true)
+    }
+
+    /// Restart the timer from zero with the timeout passed to the most
+    /// recent call to `start`.  If the timer is still running, this
+    /// extends the time until it expires.
+    pub fn retrigger(&mut self) {
+        let count = self.tc.count_16();
+        count.intflag.write(|w| w.ovf().set_bit());
+        count.ctrlbset.write(|w| w.cmd().retrigger());
+        while count.status.read().syncbusy().bit_is_set() {}
+    }
+
+    /// Stop the timer before it expires.
+    pub fn cancel(&mut self) {
+        let count = self.tc.count_16();
+        count.ctrlbset.write(|w| w.cmd().stop());
+        while count.status.read().syncbusy().bit_is_set() {}
+    }
+
+    /// Returns true if the timer has expired or been cancelled.
+    pub fn is_stopped(&self) -> bool {
+        self.tc.count_16().status.read().stop().bit_is_set()
+    }
+
+    /// Enable the interrupt generation for this hardware timer.
+    /// This method only sets the clock configuration to trigger
+    /// the interrupt; it does not configure the interrupt controller
+    /// or define an interrupt handler.
+    pub fn enable_interrupt(&mut self) {
+        self.tc.count_16().intenset.write(|w| w.ovf().set_bit());
+    }
+
+    /// Disables interrupt generation for this hardware timer.
+    /// This method only sets the clock configuration to prevent
+    /// triggering the interrupt; it does not configure the interrupt
+    /// controller.
+    pub fn disable_interrupt(&mut self) {
+        self.tc.count_16().intenclr.write(|w| w.ovf().set_bit());
+    }
+
+    /// Configure whether the timer keeps counting in standby sleep
+    /// mode, so that it can wake the device.  The timer's clock
+    /// generator must also run in standby.
+    pub fn run_in_standby(&mut self, enabled: bool) {
+        set_run_in_standby!(self.tc.count_16(), enabled);
+    }
+
+    /// Convert back into a periodic timer.  The timer must be
+    /// started again before it is used.
+    pub fn into_periodic(self) -> TimerCounter<TC> {
+        reset(self.tc.count_16());
+        TimerCounter {
+            freq: self.freq,
+            tc: self.tc,
+        }
+    }
+}
+
+/// A timer counter running in 8-bit mode, using the PER register
+/// as its TOP value.  This allows short timeouts to be generated
+/// with an 8-bit resolution while freeing both compare channels.
+/// TimerCounter8 implements both the `Periodic` and the `CountDown`
+/// embedded_hal timer traits.
+pub struct TimerCounter8<TC> {
+    freq: Hertz,
+    tc: TC,
+}
+
+impl<TC> Periodic for TimerCounter8<TC> {}
+impl<TC> CountDown for TimerCounter8<TC>
+where
+    TC: Count16 + Count8,
+{
+    type Time = Hertz;
+
+    /// Start the timer.  This panics if the timeout can't be reached;
+    /// `try_start` reports the error instead.
+    fn start<T>(&mut self, timeout: T)
+    where
+        T: Into<Hertz>,
+    {
+        if let Err(err) = self.try_start(timeout) {
+            panic!("timer can't be started: {:?}", err);
+        }
+    }
+
+    fn wait(&mut self) -> nb::Result<(), Void> {
+        let count = self.tc.count_8();
+        if count.intflag.read().ovf().bit_is_set() {
+            // Writing a 1 clears the flag
+            count.intflag.write(|w| w.ovf().set_bit());
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+impl<TC> TimerCounter8<TC>
+where
+    TC: Count16 + Count8,
+{
+    /// Start the timer, or return an error if `timeout` can't be
+    /// generated from the timer's clock.
+    pub fn try_start<T>(&mut self, timeout: T) -> Result<(), TimerError>
+    where
+        T: Into<Hertz>,
+    {
+        let params = counter_params(self.freq, timeout.into(), u8::max_value() as u32)?;
+        reset(self.tc.count_16());
+
+        // PER only exists in 8-bit mode, so the mode must be selected
+        // before it is written.  CTRLA has the same layout in every mode
+        self.tc.count_16().ctrla.modify(|_, w| {
+            w.mode().count8();
+            set_prescaler!(w, params.divider);
+            w.wavegen().nfrq()
+        });
+
+        // Overflow occurs when the count passes PER
+        let count = self.tc.count_8();
+        count
+            .per
+            .write(|w| unsafe { w.per().bits(params.top as u8) });
+        while count.status.read().syncbusy().bit_is_set() {}
+
+        count.ctrla.modify(|_, w| w.enable().set_bit());
+        while count.status.read().syncbusy().bit_is_set() {}
+        Ok(())
+    }
+
+    /// Enable the interrupt generation for this hardware timer.
+    /// This method only sets the clock configuration to trigger
+    /// the interrupt; it does not configure the interrupt controller
+    /// or define an interrupt handler.
+    pub fn enable_interrupt(&mut self) {
+        self.tc.count_8().intenset.write(|w| w.ovf().set_bit());
+    }
+
+    /// Disables interrupt generation for this hardware timer.
+    /// This method only sets the clock configuration to prevent
+    /// triggering the interrupt; it does not configure the interrupt
+    /// controller.
+    pub fn disable_interrupt(&mut self) {
+        self.tc.count_8().intenclr.write(|w| w.ovf().set_bit());
+    }
+
+    /// Configure whether the timer keeps counting in standby sleep
+    /// mode, so that it can wake the device.  The timer's clock
+    /// generator must also run in standby.
+    pub fn run_in_standby(&mut self, enabled: bool) {
+        set_run_in_standby!(self.tc.count_16(), enabled);
+    }
+
+    /// Convert back into a 16-bit timer.  The timer must be started
+    /// again before it is used.
+    pub fn into_16bit(self) -> TimerCounter<TC> {
+        reset(self.tc.count_16());
+        TimerCounter {
+            freq: self.freq,
+            tc: self.tc,
+        }
+    }
+}
+
+/// Reset the timer and start it counting freely with the prescaler
+/// from `params`.
+fn start_monotonic(count: &COUNT16, params: &clock::CounterParams) {
+    reset(count);
+    count.ctrla.modify(|_, w| {
+        set_prescaler!(w, params.divider);
+        // Count freely up to 0xffff
+        w.wavegen().nfrq();
+        w.enable().set_bit()
+    });
+    while count.status.read().syncbusy().bit_is_set() {}
+    // Keep the count synchronized so that it can be read without
+    // waiting for a read request each time
+    count.readreq.write(|w| unsafe {
+        w.rcont().set_bit();
+        w.rreq().set_bit();
+        w.addr().bits(0x10)
+    });
+}
+
+/// The overflow count and ratio of a `TimerMonotonic` clock, kept in
+/// a static so that the clock can be read through `StaticMonotonic`.
+struct MonotonicState {
+    overflows: u64,
+    ratio: Fraction,
+}
+
+impl MonotonicState {
+    const fn new() -> Self {
+        MonotonicState {
+            overflows: 0,
+            ratio: Fraction {
+                numerator: 1,
+                denominator: 1,
+            },
+        }
+    }
+
+    /// Returns the count extended by the overflow count.  Must be
+    /// called with interrupts disabled.
+    fn now(&mut self, count: &COUNT16) -> Instant {
+        let value = count.count.read().count().bits();
+        let mut ticks = self.overflows;
+        if count.intflag.read().ovf().bit_is_set() {
+            // Writing a 1 clears the flag
+            count.intflag.write(|w| w.ovf().set_bit());
+            self.overflows = self.overflows.wrapping_add(1);
+            // A count in the lower half was read after the overflow
+            if value < 1 << 15 {
+                ticks = ticks.wrapping_add(1);
+            }
+        }
+        Instant::from_ticks(ticks << 16 | value as u64)
+    }
+
+    /// Restart the count from zero.  Must be called with interrupts
+    /// disabled.
+    fn reset(&mut self, count: &COUNT16) {
+        count.count.write(|w| unsafe { w.count().bits(0) });
+        while count.status.read().syncbusy().bit_is_set() {}
+        // Writing a 1 clears the flag
+        count.intflag.write(|w| w.ovf().set_bit());
+        self.overflows = 0;
+    }
+}
+
+/// A `Monotonic` clock extending the 16-bit count of a timer counter
+/// with a count of its overflows.  `now` must be called at least once
+/// per 32768 ticks, so the overflow interrupt should normally be
+/// enabled.
+pub struct TimerMonotonic<TC> {
+    src_freq: Hertz,
+    freq: Hertz,
+    tc: TC,
+}
+
+impl<TC> TimerMonotonic<TC>
+where
+    TC: Count16,
+{
+    /// Enable the interrupt generation for counter overflows, which
+    /// should call `now` to record the overflow.
+    /// This method only sets the clock configuration to trigger
+    /// the interrupt; it does not configure the interrupt controller
+    /// or define an interrupt handler.
+    pub fn enable_interrupt(&mut self) {
+        self.tc.count_16().intenset.write(|w| w.ovf().set_bit());
+    }
+
+    /// Disables interrupt generation for counter overflows.
+    /// This method only sets the clock configuration to prevent
+    /// triggering the interrupt; it does not configure the interrupt
+    /// controller.
+    pub fn disable_interrupt(&mut self) {
+        self.tc.count_16().intenclr.write(|w| w.ovf().set_bit());
+    }
+
+    /// Stop the clock and return the timer.
+    pub fn free(self) -> TimerCounter<TC> {
+        reset(self.tc.count_16());
+        TimerCounter {
+            freq: self.src_freq,
+            tc: self.tc,
+        }
+    }
+}
+
+/// A pair of hardware timer counters running as a single 32-bit
+/// counter.  The master instance controls the pair; the slave
+/// instance is held to prevent it being used independently.
+/// TimerCounter32 implements both the `Periodic` and the `CountDown`
+/// embedded_hal timer traits.
+pub struct TimerCounter32<MASTER, SLAVE> {
+    freq: Hertz,
+    master: MASTER,
+    slave: SLAVE,
+}
+
+/// This is a helper trait to make it easier to make most of the
+/// TimerCounter32 impl generic.  It doesn't make too much sense to
+/// to try to implement this trait outside of this module.
+pub trait Count32 {
+    fn count_32(&self) -> &COUNT32;
+}
+
+impl<MASTER, SLAVE> Periodic for TimerCounter32<MASTER, SLAVE> {}
+impl<MASTER, SLAVE> CountDown for TimerCounter32<MASTER, SLAVE>
+where
+    MASTER: Count32,
+{
+    type Time = Hertz;
+
+    /// Start the timer.  This panics if the timeout can't be reached;
+    /// `try_start` reports the error instead.
+    fn start<T>(&mut self, timeout: T)
+    where
+        T: Into<Hertz>,
+    {
+        if let Err(err) = self.try_start(timeout) {
+            panic!("timer can't be started: {:?}", err);
+        }
+    }
+
+    fn wait(&mut self) -> nb::Result<(), Void> {
+        let count = self.master.count_32();
+        if count.intflag.read().ovf().bit_is_set() {
+            // Writing a 1 clears the flag
+            count.intflag.modify(|_, w| w.ovf().set_bit());
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+impl<MASTER, SLAVE> TimerCounter32<MASTER, SLAVE>
+where
+    MASTER: Count32,
+{
+    /// Start the timer, or return an error if `timeout` can't be
+    /// generated from the timer's clock.
+    pub fn try_start<T>(&mut self, timeout: T) -> Result<(), TimerError>
+    where
+        T: Into<Hertz>,
+    {
+        let timeout = timeout.into();
+        let params = counter_params(self.freq, timeout, u32::max_value())?;
+        let count = self.master.count_32();
+        let standby = count.ctrla.read().runstdby().bit_is_set();
 
         // Disable the timer while we reconfigure it
         count.ctrla.modify(|_, w| w.enable().clear_bit());
         while count.status.read().syncbusy().bit_is_set() {}
 
-        // Now that we have a clock routed to the peripheral, we
-        // can ask it to perform a reset.
         count.ctrla.write(|w| w.swrst().set_bit());
         while count.status.read().syncbusy().bit_is_set() {}
         // the SVD erroneously marks swrst as write-only, so we
         // need to manually read the bit here
         while count.ctrla.read().bits() & 1 != 0 {}
 
+        // The pairing with the slave only exists in 32-bit mode, so
+        // the mode must be selected before the 32-bit TOP is written
+        count.ctrla.write(|w| {
+            w.mode().count32();
+            // Keep the setting made by `run_in_standby`
+            w.runstdby().bit(standby);
+            set_prescaler!(w, params.divider);
+            // Enable Match Frequency Waveform generation
+            w.wavegen().mfrq()
+        });
+
         count.ctrlbset.write(|w| {
             // Count up when the direction bit is zero
             w.dir().clear_bit();
@@ -70,73 +669,251 @@ where
             w.oneshot().clear_bit()
         });
 
-        // How many cycles of the clock need to happen to reach our
+        // Set TOP value for mfrq mode
+        count.cc[0].write(|w| unsafe { w.cc().bits(params.top) });
+        while count.status.read().syncbusy().bit_is_set() {}
+
+        count.ctrla.modify(|_, w| w.enable().set_bit());
+        while count.status.read().syncbusy().bit_is_set() {}
+        Ok(())
+    }
 
-        // effective value.
-        let cycles = params.effective_freq.0 / timeout.0;
-        if cycles > u16::max_value() as u32 {
-            panic!(
-                "cycles {} is out of range for a 16 bit counter (timeout={})",
-                cycles, timeout.0
-            );
-        }
+    /// Enable the interrupt generation for this hardware timer.
+    /// This method only sets the clock configuration to trigger
+    /// the interrupt; it does not configure the interrupt controller
+    /// or define an interrupt handler.
+    pub fn enable_interrupt(&mut self) {
+        self.master.count_32().intenset.write(|w| w.ovf().set_bit());
+    }
 
-        // Set TOP value for mfrq mode
-        count.cc[0].write(|w| unsafe { w.cc().bits(cycles as u16) });
+    /// Disables interrupt generation for this hardware timer.
+    /// This method only sets the clock configuration to prevent
+    /// triggering the interrupt; it does not configure the interrupt
+    /// controller.
+    pub fn disable_interrupt(&mut self) {
+        self.master.count_32().intenclr.write(|w| w.ovf().set_bit());
+    }
 
-        count.ctrla.modify(|_, w| {
-            match divider {
-                1 => w.prescaler().div1(),
-                2 => w.prescaler().div2(),
-                4 => w.prescaler().div4(),
-                8 => w.prescaler().div8(),
-                16 => w.prescaler().div16(),
-                64 => w.prescaler().div64(),
-                256 => w.prescaler().div256(),
-                1024 => w.prescaler().div1024(),
-                _ => unreachable!(),
-            };
-            // Enable Match Frequency Waveform generation
-            w.wavegen().mfrq();
-            w.enable().set_bit()
-        });
+    /// Configure whether the timer keeps counting in standby sleep
+    /// mode, so that it can wake the device.  The timers' clock
+    /// generator must also run in standby.
+    pub fn run_in_standby(&mut self, enabled: bool) {
+        set_run_in_standby!(self.master.count_32(), enabled);
     }
 
-    fn wait(&mut self) -> nb::Result<(), Void> {
+    /// Stop the timer and return the underlying timer instances.
+    pub fn free(self) -> (MASTER, SLAVE) {
+        let count = self.master.count_32();
+        count.ctrla.modify(|_, w| w.enable().clear_bit());
+        while count.status.read().syncbusy().bit_is_set() {}
+        count.ctrla.write(|w| w.swrst().set_bit());
+        while count.status.read().syncbusy().bit_is_set() {}
+        (self.master, self.slave)
+    }
+}
+
+#[derive(Debug)]
+pub enum CaptureError {
+    /// A new value was captured before the previous one was read
+    Overrun,
+}
+
+/// A timer counter capturing its count each time an event arrives
+/// on its event input, which is usually routed from an external
+/// interrupt line via the event system.
+/// The counter runs freely, wrapping after 65536 ticks.
+pub struct InputCapture<TC, U> {
+    src_freq: Hertz,
+    freq: Hertz,
+    tc: TC,
+    user: ConnectedUser<U>,
+}
+
+impl<TC, U> InputCapture<TC, U>
+where
+    TC: Count16,
+{
+    /// Returns the rate at which the counter ticks
+    pub fn tick_freq(&self) -> Hertz {
+        self.freq
+    }
+
+    /// Returns the count captured by the most recent event.
+    pub fn read(&mut self) -> nb::Result<u16, CaptureError> {
         let count = self.tc.count_16();
-        if count.intflag.read().ovf().bit_is_set() {
+        let flags = count.intflag.read();
+        if flags.err().bit_is_set() {
             // Writing a 1 clears the flag
-            count.intflag.modify(|_, w| w.ovf().set_bit());
-            Ok(())
+            count.intflag.write(|w| w.err().set_bit());
+            return Err(nb::Error::Other(CaptureError::Overrun));
+        }
+        if flags.mc0().bit_is_set() {
+            // Reading the captured value clears the flag
+            Ok(count.cc[0].read().cc().bits())
         } else {
             Err(nb::Error::WouldBlock)
         }
     }
-}
 
-impl<TC> TimerCounter<TC>
-where
-    TC: Count16,
-{
-    /// Enable the interrupt generation for this hardware timer.
+    /// Enable the interrupt generation for captured values.
     /// This method only sets the clock configuration to trigger
     /// the interrupt; it does not configure the interrupt controller
     /// or define an interrupt handler.
     pub fn enable_interrupt(&mut self) {
-        self.tc.count_16().intenset.write(|w| w.ovf().set_bit());
+        self.tc.count_16().intenset.write(|w| w.mc0().set_bit());
     }
 
-    /// Disables interrupt generation for this hardware timer.
+    /// Disables interrupt generation for captured values.
     /// This method only sets the clock configuration to prevent
     /// triggering the interrupt; it does not configure the interrupt
     /// controller.
     pub fn disable_interrupt(&mut self) {
-        self.tc.count_16().intenclr.write(|w| w.ovf().set_bit());
+        self.tc.count_16().intenclr.write(|w| w.mc0().set_bit());
+    }
+
+    /// Configure whether the timer keeps counting in standby sleep
+    /// mode, so that events can be captured while the device sleeps.
+    /// The timer's clock generator must also run in standby.
+    pub fn run_in_standby(&mut self, enabled: bool) {
+        set_run_in_standby!(self.tc.count_16(), enabled);
+    }
+
+    /// Stop capturing and return the timer and the event user.
+    pub fn free(self) -> (TimerCounter<TC>, ConnectedUser<U>) {
+        reset(self.tc.count_16());
+        let timer = TimerCounter {
+            freq: self.src_freq,
+            tc: self.tc,
+        };
+        (timer, self.user)
     }
 }
 
+/// The period and pulse width of a signal, in ticks of the
+/// measuring timer
+#[derive(Clone, Copy, Debug)]
+pub struct Measurement {
+    pub period: u16,
+    pub pulse_width: u16,
+    pub tick_freq: Hertz,
+}
+
+impl Measurement {
+    /// Returns the frequency of the measured signal
+    pub fn freq(&self) -> Hertz {
+        Hertz(self.tick_freq.0 / (self.period as u32).max(1))
+    }
+
+    /// Returns the duty cycle of the measured signal, scaled so that
+    /// a signal that is always active has a duty of `max_duty`.
+    pub fn duty(&self, max_duty: u32) -> u32 {
+        let duty = self.pulse_width as u64 * max_duty as u64 / (self.period as u64).max(1);
+        duty.min(max_duty as u64) as u32
+    }
+
+    /// Returns the pulse width in microseconds
+    pub fn pulse_width_us(&self) -> u32 {
+        (self.pulse_width as u64 * 1_000_000 / self.tick_freq.0 as u64) as u32
+    }
+}
+
+/// A timer counter measuring the period and pulse width of the
+/// signal on its event input, using the PPW event action.
+/// Each active edge restarts the counter; the longest measurable
+/// period is 65535 ticks.
+pub struct PulseMeasurement<TC, U> {
+    src_freq: Hertz,
+    freq: Hertz,
+    tc: TC,
+    user: ConnectedUser<U>,
+}
+
+impl<TC, U> PulseMeasurement<TC, U>
+where
+    TC: Count16,
+{
+    /// Returns the rate at which the counter ticks
+    pub fn tick_freq(&self) -> Hertz {
+        self.freq
+    }
+
+    /// Returns the most recent measurement once both the period and
+    /// the pulse width have been captured.
+    pub fn read(&mut self) -> nb::Result<Measurement, CaptureError> {
+        let count = self.tc.count_16();
+        let flags = count.intflag.read();
+        if flags.err().bit_is_set() {
+            // Writing a 1 clears the flag
+            count.intflag.write(|w| w.err().set_bit());
+            return Err(nb::Error::Other(CaptureError::Overrun));
+        }
+        if flags.mc0().bit_is_set() && flags.mc1().bit_is_set() {
+            // Reading the captured values clears the flags
+            Ok(Measurement {
+                period: count.cc[0].read().cc().bits(),
+                pulse_width: count.cc[1].read().cc().bits(),
+                tick_freq: self.freq,
+            })
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    /// Configure whether the timer keeps counting in standby sleep
+    /// mode, so that events can be measured while the device sleeps.
+    /// The timer's clock generator must also run in standby.
+    pub fn run_in_standby(&mut self, enabled: bool) {
+        set_run_in_standby!(self.tc.count_16(), enabled);
+    }
+
+    /// Stop measuring and return the timer and the event user.
+    pub fn free(self) -> (TimerCounter<TC>, ConnectedUser<U>) {
+        reset(self.tc.count_16());
+        let timer = TimerCounter {
+            freq: self.src_freq,
+            tc: self.tc,
+        };
+        (timer, self.user)
+    }
+}
+
+/// Reset the timer and configure it to count freely at the prescaled
+/// clock rate closest to `resolution`, acting on its event input.
+/// Returns the actual tick frequency, or an error without touching the
+/// timer if `resolution` is outside the range of the prescalers.
+fn configure_capture(
+    count: &COUNT16,
+    freq: Hertz,
+    resolution: Hertz,
+    ppw: bool,
+) -> Result<Hertz, TimerError> {
+    let params = tick_params(freq, resolution)?;
+    reset(count);
+    count.evctrl.write(|w| {
+        w.tcei().set_bit();
+        if ppw {
+            w.evact().ppw()
+        } else {
+            w.evact().off()
+        }
+    });
+    count.ctrlc.write(|w| {
+        w.cpten0().set_bit();
+        w.cpten1().bit(ppw)
+    });
+    while count.status.read().syncbusy().bit_is_set() {}
+    count.ctrla.modify(|_, w| {
+        set_prescaler!(w, params.divider);
+        // Count freely up to 0xffff
+        w.wavegen().nfrq();
+        w.enable().set_bit()
+    });
+    while count.status.read().syncbusy().bit_is_set() {}
+    Ok(params.effective_freq)
+}
+
 macro_rules! tc {
-    ($($TYPE:ident: ($TC:ident, $pm:ident, $clock:ident),)+) => {
+    ($($TYPE:ident: ($TC:ident, $pm:ident, $clock:ident, $User:ident),)+) => {
         $(
 pub type $TYPE = TimerCounter<$TC>;
 
@@ -146,6 +923,12 @@ impl Count16 for $TC {
     }
 }
 
+impl Count8 for $TC {
+    fn count_8(&self) -> &COUNT8 {
+        self.count8()
+    }
+}
+
 impl TimerCounter<$TC>
 {
     /// Configure this timer counter instance.
@@ -169,13 +952,267 @@ impl TimerCounter<$TC>
             tc,
         }
     }
+
+    /// Convert into a free running `Monotonic` clock ticking at the
+    /// prescaled clock rate closest to `rate`.  `core_freq` is the
+    /// rate of the processor clock, from which
+    /// `StaticMonotonic::ratio` is computed.
+    /// The rate actually chosen is reported by `TimerMonotonic::freq`.
+    /// Returns the timer together with the error if `rate` is outside
+    /// the range of the prescalers.
+    pub fn into_monotonic<T, C>(
+        self,
+        rate: T,
+        core_freq: C,
+    ) -> Result<TimerMonotonic<$TC>, (Self, TimerError)>
+    where
+        T: Into<Hertz>,
+        C: Into<Hertz>,
+    {
+        // The counter wraps freely, so each tick is one prescaled cycle
+        let params = match tick_params(self.freq, rate.into()) {
+            Ok(params) => params,
+            Err(err) => return Err((self, err)),
+        };
+        start_monotonic(self.tc.count_16(), &params);
+        let ratio = Fraction::of(core_freq.into(), params.effective_freq);
+        interrupt::free(|_| {
+            let state = TimerMonotonic::<$TC>::state();
+            state.overflows = 0;
+            state.ratio = ratio;
+        });
+        Ok(TimerMonotonic {
+            src_freq: self.freq,
+            freq: params.effective_freq,
+            tc: self.tc,
+        })
+    }
+
+    /// Capture the count each time an event arrives from the event
+    /// system.  The counter ticks at the prescaled clock rate closest
+    /// to `resolution`; the exact rate is reported by `tick_freq`.
+    /// Returns the timer and event user together with the error if
+    /// `resolution` is faster than the timer's clock or slower than
+    /// the largest prescaler can produce.
+    pub fn into_capture<T>(
+        self,
+        resolution: T,
+        user: ConnectedUser<$User>,
+    ) -> Result<InputCapture<$TC, $User>, (Self, ConnectedUser<$User>, TimerError)>
+    where
+        T: Into<Hertz>,
+    {
+        match configure_capture(self.tc.count_16(), self.freq, resolution.into(), false) {
+            Ok(freq) => Ok(InputCapture {
+                src_freq: self.freq,
+                freq,
+                tc: self.tc,
+                user,
+            }),
+            Err(err) => Err((self, user, err)),
+        }
+    }
+
+    /// Measure the period and pulse width of the signal arriving from
+    /// the event system.  The period is measured between rising
+    /// edges of the event signal and the pulse width is the time
+    /// that it is high.  When routing an external interrupt line,
+    /// configure it to sense both edges and use an asynchronous
+    /// event channel.
+    /// The counter ticks at the prescaled clock rate closest to
+    /// `resolution`; the exact rate is reported by `tick_freq`.
+    /// Returns the timer and event user together with the error if
+    /// `resolution` is faster than the timer's clock or slower than
+    /// the largest prescaler can produce.
+    pub fn into_pulse_measurement<T>(
+        self,
+        resolution: T,
+        user: ConnectedUser<$User>,
+    ) -> Result<PulseMeasurement<$TC, $User>, (Self, ConnectedUser<$User>, TimerError)>
+    where
+        T: Into<Hertz>,
+    {
+        match configure_capture(self.tc.count_16(), self.freq, resolution.into(), true) {
+            Ok(freq) => Ok(PulseMeasurement {
+                src_freq: self.freq,
+                freq,
+                tc: self.tc,
+                user,
+            }),
+            Err(err) => Err((self, user, err)),
+        }
+    }
+}
+
+impl TimerMonotonic<$TC> {
+    /// Returns the state of this clock.  Must only be used with
+    /// interrupts disabled.
+    fn state() -> &'static mut MonotonicState {
+        static mut STATE: MonotonicState = MonotonicState::new();
+        unsafe { &mut STATE }
+    }
+}
+
+impl Monotonic for TimerMonotonic<$TC> {
+    fn freq(&self) -> Hertz {
+        self.freq
+    }
+
+    fn now(&mut self) -> Instant {
+        <Self as StaticMonotonic>::now()
+    }
+}
+
+impl StaticMonotonic for TimerMonotonic<$TC> {
+    type Instant = Instant;
+
+    fn ratio() -> Fraction {
+        interrupt::free(|_| Self::state().ratio)
+    }
+
+    fn now() -> Instant {
+        // this is safe because the TimerMonotonic owns the timer
+        let count = unsafe { &*$TC::ptr() }.count16();
+        interrupt::free(|_| Self::state().now(count))
+    }
+
+    unsafe fn reset() {
+        let count = (*$TC::ptr()).count16();
+        interrupt::free(|_| Self::state().reset(count));
+    }
+
+    fn zero() -> Instant {
+        Instant::from_ticks(0)
+    }
 }
         )+
     }
 }
 
 tc! {
-    TimerCounter3: (TC3, tc3_, Tcc2Tc3Clock),
-    TimerCounter4: (TC4, tc4_, Tc4Tc5Clock),
-    TimerCounter5: (TC5, tc5_, Tc4Tc5Clock),
+    TimerCounter3: (TC3, tc3_, Tcc2Tc3Clock, Tc3User),
+    TimerCounter4: (TC4, tc4_, Tc4Tc5Clock, Tc4User),
+    TimerCounter5: (TC5, tc5_, Tc4Tc5Clock, Tc5User),
+}
+
+macro_rules! tc32 {
+    ($($TYPE:ident: ($MASTER:ident, $SLAVE:ident, $pm_master:ident, $pm_slave:ident,
+        $clock:ident),)+) => {
+        $(
+pub type $TYPE = TimerCounter32<$MASTER, $SLAVE>;
+
+impl Count32 for $MASTER {
+    fn count_32(&self) -> &COUNT32 {
+        self.count32()
+    }
+}
+
+impl TimerCounter32<$MASTER, $SLAVE>
+{
+    /// Configure this pair of timer counter instances.
+    /// The clock is obtained from the `GenericClockController` instance
+    /// and sets the resolution of the timeout values that can be
+    /// passed to the `start` method.
+    pub fn $pm_master(
+        clock: &clock::$clock,
+        master: $MASTER,
+        slave: $SLAVE,
+        pm: &mut PM,
+    ) -> Self {
+        // Both instances must be powered for the pair to operate
+        pm.apbcmask.modify(|_, w| {
+            w.$pm_master().set_bit();
+            w.$pm_slave().set_bit()
+        });
+        {
+            let count = master.count_32();
+
+            // Disable the timer while we reconfigure it
+            count.ctrla.modify(|_, w| w.enable().clear_bit());
+            while count.status.read().syncbusy().bit_is_set() {}
+        }
+        Self {
+            freq: clock.freq(),
+            master,
+            slave,
+        }
+    }
+}
+        )+
+    }
+}
+
+tc32! {
+    TimerCounter45: (TC4, TC5, tc4_, tc5_, Tc4Tc5Clock),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{counter_params, tick_params, TimerError};
+    use time::Hertz;
+
+    #[test]
+    fn reachable_timeout() {
+        let params = counter_params(Hertz(48_000_000), Hertz(1_000), 0xffff).unwrap();
+        assert_eq!(params.divider, 1);
+        assert_eq!(params.top, 47_999);
+    }
+
+    #[test]
+    fn too_high() {
+        assert_eq!(
+            counter_params(Hertz(1_000_000), Hertz(3_000_000), 0xffff).unwrap_err(),
+            TimerError::FrequencyTooHigh {
+                requested: Hertz(3_000_000),
+                max: Hertz(1_000_000),
+            }
+        );
+    }
+
+    #[test]
+    fn too_low() {
+        // 48Mhz / 1024 / 256 is just over 183Hz
+        assert_eq!(
+            counter_params(Hertz(48_000_000), Hertz(1), 0xff).unwrap_err(),
+            TimerError::FrequencyTooLow {
+                requested: Hertz(1),
+                min: Hertz(184),
+            }
+        );
+    }
+
+    #[test]
+    fn between_prescalers() {
+        // With a single cycle per period only the prescaled rates exist
+        assert_eq!(
+            counter_params(Hertz(48_000_000), Hertz(2_000_000), 0).unwrap_err(),
+            TimerError::FrequencyUnreachable {
+                requested: Hertz(2_000_000),
+            }
+        );
+    }
+
+    #[test]
+    fn tick_rate_between_prescalers() {
+        let params = tick_params(Hertz(48_000_000), Hertz(100_000)).unwrap();
+        assert_eq!(params.effective_freq, Hertz(46_875));
+    }
+
+    #[test]
+    fn tick_rate_out_of_range() {
+        assert_eq!(
+            tick_params(Hertz(48_000_000), Hertz(10_000)).unwrap_err(),
+            TimerError::FrequencyTooLow {
+                requested: Hertz(10_000),
+                min: Hertz(46_875),
+            }
+        );
+        assert_eq!(
+            tick_params(Hertz(48_000_000), Hertz(96_000_000)).unwrap_err(),
+            TimerError::FrequencyTooHigh {
+                requested: Hertz(96_000_000),
+                max: Hertz(48_000_000),
+            }
+        );
+    }
 }
@@ -0,0 +1,263 @@
+//! Working with the Watchdog Timer.
+//! The WDT resets the device unless it is fed periodically.  Its
+//! periods are counted in cycles of the WDT generic clock, which is
+//! normally derived from the ultra low power 32kHz oscillator; at
+//! reset it runs at 1024Hz from GCLK2.
+//! The watchdog may have been enabled in always-on mode by the user
+//! row fuses, in which case it can't be disabled.
+use clock;
+#[cfg(feature = "unproven")]
+use hal::watchdog;
+use target_device::{PM, WDT};
+use time::{Hertz, Milliseconds};
+
+/// The error returned when the watchdog can't be disabled because it
+/// is in always-on mode.
+#[derive(Debug)]
+pub struct AlwaysOnError;
+
+/// The error returned when the watchdog can't be configured as
+/// requested.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchdogError {
+    /// The period is longer than `MAX_PERIOD_CYCLES`, which at the
+    /// current clock rate is `max`.
+    PeriodTooLong {
+        requested: Milliseconds,
+        max: Milliseconds,
+    },
+    /// The early warning offset isn't shorter than the timeout.
+    OffsetTooLong,
+    /// The watchdog is in always-on mode, so its early warning offset
+    /// can't be changed.
+    AlwaysOn,
+}
+
+/// The longest period of the watchdog, in cycles of its clock.
+pub const MAX_PERIOD_CYCLES: u32 = 16384;
+
+/// `Watchdog` represents the WDT peripheral.
+pub struct Watchdog {
+    wdt: WDT,
+    freq: Hertz,
+}
+
+/// Returns the CONFIG encoding of the shortest period, between 8 and
+/// `MAX_PERIOD_CYCLES` cycles, that is at least `duration` long.
+fn period_bits(freq: Hertz, duration: Milliseconds) -> Result<u8, WatchdogError> {
+    let cycles = duration.0 as u64 * freq.0 as u64 / 1_000;
+    (0..12)
+        .find(|&n| 8u64 << n >= cycles)
+        .ok_or(WatchdogError::PeriodTooLong {
+            requested: duration,
+            max: Milliseconds((MAX_PERIOD_CYCLES as u64 * 1_000 / freq.0 as u64) as u32),
+        })
+}
+
+impl Watchdog {
+    /// Power up the WDT.  The watchdog isn't started until `start` is
+    /// called, unless it was already enabled by the fuses.
+    /// The clock is obtained from the `GenericClockController` instance.
+    pub fn new(clock: &clock::WdtClock, wdt: WDT, pm: &mut PM) -> Self {
+        // this is safe because we're constrained to just the wdt bit
+        pm.apbamask.modify(|_, w| w.wdt_().set_bit());
+        Self {
+            wdt,
+            freq: clock.freq(),
+        }
+    }
+
+    fn wait_for_sync(&self) {
+        while self.wdt.status.read().syncbusy().bit_is_set() {}
+    }
+
+    /// Returns true if the watchdog is in always-on mode, either
+    /// because of the fuses or because `start_always_on` was called.
+    pub fn is_always_on(&self) -> bool {
+        self.wdt.ctrl.read().alwayson().bit_is_set()
+    }
+
+    /// Returns the longest period that the watchdog can count at the
+    /// rate of its clock.
+    pub fn max_period(&self) -> Milliseconds {
+        Milliseconds((MAX_PERIOD_CYCLES as u64 * 1_000 / self.freq.0 as u64) as u32)
+    }
+
+    /// Returns true if the watchdog is running
+    pub fn is_enabled(&self) -> bool {
+        self.wdt.ctrl.read().enable().bit_is_set() || self.is_always_on()
+    }
+
+    /// Configure the periods.  The watchdog must be disabled unless
+    /// it is in always-on mode.
+    fn configure(&mut self, per: u8, closed: Option<u8>) {
+        self.wdt.config.write(|w| unsafe {
+            w.per().bits(per);
+            w.window().bits(closed.unwrap_or(0))
+        });
+        self.wdt.ctrl.modify(|_, w| w.wen().bit(closed.is_some()));
+        self.wait_for_sync();
+    }
+
+    fn enable(&mut self) {
+        if !self.is_always_on() {
+            self.wdt.ctrl.modify(|_, w| w.enable().set_bit());
+            self.wait_for_sync();
+        }
+    }
+
+    /// Start the watchdog, which resets the device unless it is fed
+    /// within `timeout`.  The timeout is rounded up to a power of two
+    /// number of clock cycles.  Returns an error, without changing
+    /// the watchdog, if the timeout is longer than `max_period`.
+    pub fn start<T: Into<Milliseconds>>(&mut self, timeout: T) -> Result<(), WatchdogError> {
+        let per = period_bits(self.freq, timeout.into())?;
+        let _ = self.disable();
+        self.configure(per, None);
+        self.enable();
+        Ok(())
+    }
+
+    /// Start the watchdog in window mode.  Feeding the watchdog during
+    /// the `closed` period, or failing to feed it before the end of
+    /// the following `open` period, resets the device.  Returns an
+    /// error, without changing the watchdog, if either period is
+    /// longer than `max_period`.
+    pub fn start_windowed<T: Into<Milliseconds>>(
+        &mut self,
+        closed: T,
+        open: T,
+    ) -> Result<(), WatchdogError> {
+        let closed = period_bits(self.freq, closed.into())?;
+        let per = period_bits(self.freq, open.into())?;
+        let _ = self.disable();
+        self.configure(per, Some(closed));
+        self.enable();
+        Ok(())
+    }
+
+    /// Start the watchdog in always-on mode, in which it can't be
+    /// disabled or reconfigured until the device is reset.  Returns
+    /// an error, without changing the watchdog, if the timeout is
+    /// longer than `max_period`.
+    pub fn start_always_on<T: Into<Milliseconds>>(
+        &mut self,
+        timeout: T,
+    ) -> Result<(), WatchdogError> {
+        let per = period_bits(self.freq, timeout.into())?;
+        let _ = self.disable();
+        self.configure(per, None);
+        self.wdt.ctrl.modify(|_, w| w.alwayson().set_bit());
+        self.wait_for_sync();
+        Ok(())
+    }
+
+    /// Restart the watchdog period, preventing a reset
+    pub fn feed(&mut self) {
+        self.wdt.clear.write(|w| w.clear().key());
+        self.wait_for_sync();
+    }
+
+    /// Stop the watchdog.  This fails if the watchdog is in always-on
+    /// mode.
+    pub fn disable(&mut self) -> Result<(), AlwaysOnError> {
+        if self.is_always_on() {
+            return Err(AlwaysOnError);
+        }
+        self.wdt.ctrl.modify(|_, w| w.enable().clear_bit());
+        self.wait_for_sync();
+        Ok(())
+    }
+
+    /// Configure the early warning interrupt to trigger `offset` into
+    /// the watchdog period, giving the application a chance to save
+    /// its state or feed the watchdog before a reset.  In window mode
+    /// the offset is measured from the start of the open period.
+    /// The offset is rounded up to a power of two number of clock
+    /// cycles and must be shorter than the configured timeout.  The
+    /// offset can only be changed while the watchdog is disabled, so
+    /// a running watchdog is briefly stopped, which restarts its
+    /// period; this fails if it is in always-on mode.
+    /// This method only sets the WDT configuration to trigger the
+    /// interrupt; it does not configure the interrupt controller or
+    /// define an interrupt handler.
+    pub fn enable_early_warning<T: Into<Milliseconds>>(
+        &mut self,
+        offset: T,
+    ) -> Result<(), WatchdogError> {
+        let offset = period_bits(self.freq, offset.into())?;
+        if offset >= self.wdt.config.read().per().bits() {
+            return Err(WatchdogError::OffsetTooLong);
+        }
+        if self.is_always_on() {
+            return Err(WatchdogError::AlwaysOn);
+        }
+        let enabled = self.is_enabled();
+        let _ = self.disable();
+        self.wdt
+            .ewctrl
+            .write(|w| unsafe { w.ewoffset().bits(offset) });
+        if enabled {
+            self.enable();
+        }
+        self.wdt.intenset.write(|w| w.ew().set_bit());
+        Ok(())
+    }
+
+    /// Disables the early warning interrupt.
+    /// This method only sets the WDT configuration to prevent
+    /// triggering the interrupt; it does not configure the interrupt
+    /// controller.
+    pub fn disable_early_warning(&mut self) {
+        self.wdt.intenclr.write(|w| w.ew().set_bit());
+    }
+
+    /// Returns true if the early warning has triggered
+    pub fn is_early_warning(&self) -> bool {
+        self.wdt.intflag.read().ew().bit_is_set()
+    }
+
+    /// Clears the early warning interrupt flag
+    pub fn clear_early_warning(&mut self) {
+        // Writing a 1 clears the flag
+        self.wdt.intflag.write(|w| w.ew().set_bit());
+    }
+
+    /// Return the WDT peripheral.  The watchdog is left running if it
+    /// was started.
+    pub fn free(self) -> WDT {
+        self.wdt
+    }
+}
+
+#[cfg(feature = "unproven")]
+impl watchdog::Watchdog for Watchdog {
+    fn feed(&mut self) {
+        Watchdog::feed(self)
+    }
+}
+
+#[cfg(feature = "unproven")]
+impl watchdog::WatchdogEnable for Watchdog {
+    type Time = Milliseconds;
+
+    /// Start the watchdog.  Panics if the period is longer than
+    /// `max_period`; use the inherent `start` method to handle that
+    /// case.
+    fn start<T>(&mut self, period: T)
+    where
+        T: Into<Milliseconds>,
+    {
+        Watchdog::start(self, period).expect("watchdog period too long")
+    }
+}
+
+#[cfg(feature = "unproven")]
+impl watchdog::WatchdogDisable for Watchdog {
+    /// Stop the watchdog.  This has no effect if the watchdog is in
+    /// always-on mode; use the inherent `disable` method to detect
+    /// that case.
+    fn disable(&mut self) {
+        let _ = Watchdog::disable(self);
+    }
+}